@@ -0,0 +1,16 @@
+// Anonymous consts all have the same name, so the items in them must not share their paths.
+
+const _: () = {
+    /// ~MINIMIZE-ROOT kept-helper
+    struct Helper;
+};
+
+const _: () = {
+    /// ~REQUIRE-DELETED deleted-helper
+    struct Helper;
+};
+
+/// ~MINIMIZE-ROOT main
+fn main() {
+    "~MINIMIZE-ROOT main-body";
+}
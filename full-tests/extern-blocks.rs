@@ -0,0 +1,18 @@
+/// ~REQUIRE-DELETED extern-block
+unsafe extern "C" {
+    /// ~REQUIRE-DELETED foreign-fn
+    fn abs(x: i32) -> i32;
+    /// ~REQUIRE-DELETED foreign-static
+    static errno: i32;
+}
+
+unsafe extern "C" {
+    /// ~MINIMIZE-ROOT kept-foreign-fn
+    fn labs(x: i64) -> i64;
+}
+
+/// ~MINIMIZE-ROOT main
+fn main() {
+    "~MINIMIZE-ROOT main-body";
+    unsafe { labs(0) };
+}
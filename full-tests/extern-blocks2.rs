@@ -0,0 +1,18 @@
+// Both blocks are empty at some point. They must be separate sites, so that only the second one is deleted.
+
+/// ~MINIMIZE-ROOT kept-extern-block
+unsafe extern "C" {
+    /// ~REQUIRE-DELETED first-foreign-fn
+    fn abs(x: i32) -> i32;
+}
+
+/// ~REQUIRE-DELETED second-extern-block
+unsafe extern "C" {
+    /// ~REQUIRE-DELETED second-foreign-fn
+    fn labs(x: i64) -> i64;
+}
+
+/// ~MINIMIZE-ROOT main
+fn main() {
+    "~MINIMIZE-ROOT main-body";
+}
@@ -0,0 +1,15 @@
+// Deleting the first empty block must not change which block the paths of the others point to.
+
+/// ~REQUIRE-DELETED first-extern-block
+unsafe extern "C" {}
+
+/// ~MINIMIZE-ROOT kept-extern-block
+unsafe extern "C" {}
+
+/// ~REQUIRE-DELETED third-extern-block
+unsafe extern "C" {}
+
+/// ~MINIMIZE-ROOT main
+fn main() {
+    "~MINIMIZE-ROOT main-body";
+}
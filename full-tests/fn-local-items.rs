@@ -0,0 +1,28 @@
+struct S;
+
+impl S {
+    /// ~REQUIRE-DELETED impl-const
+    const UNUSED: u8 = 0;
+
+    /// ~MINIMIZE-ROOT method
+    fn method(&self) {
+        "~MINIMIZE-ROOT method-body";
+        /// ~REQUIRE-DELETED method-local-fn
+        fn method_local() {}
+    }
+}
+
+const _: () = {
+    /// ~REQUIRE-DELETED const-block-struct
+    struct InConst;
+};
+
+/// ~MINIMIZE-ROOT main
+fn main() {
+    "~MINIMIZE-ROOT main-body";
+    /// ~REQUIRE-DELETED local-struct
+    struct Local;
+    /// ~REQUIRE-DELETED local-impl
+    impl Local {}
+    S.method();
+}
@@ -0,0 +1,6 @@
+/// ~MINIMIZE-ROOT main
+fn main() {
+    "~MINIMIZE-ROOT main-body";
+    let first = |pair: (u8, &str)| (pair.0, "~REQUIRE-DELETED closure-body").0;
+    first((0, ""));
+}
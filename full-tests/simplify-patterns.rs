@@ -0,0 +1,15 @@
+struct Pair(u8, &'static str);
+
+/// ~MINIMIZE-ROOT main
+fn main() {
+    "~MINIMIZE-ROOT main-body";
+    let pair = Pair(0, "");
+    if let Pair(a, "~REQUIRE-DELETED if-let-lit") = pair {
+        "~MINIMIZE-ROOT if-let-body";
+        let _ = a;
+    }
+    match pair.1 {
+        "~REQUIRE-DELETED arm-lit" => {}
+        _ => {}
+    }
+}
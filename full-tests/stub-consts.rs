@@ -0,0 +1,13 @@
+/// ~MINIMIZE-ROOT LEN
+const LEN: usize = "~REQUIRE-DELETED const-init".len();
+
+/// ~MINIMIZE-ROOT NAME
+static NAME: &str = "~REQUIRE-DELETED static-init";
+
+/// ~MINIMIZE-ROOT main
+fn main() {
+    "~MINIMIZE-ROOT main-body";
+    let _ = [0u8; "~REQUIRE-DELETED repeat-len".len()];
+    let _: Option<[u8; "~REQUIRE-DELETED type-len".len()]> = None;
+    let _ = LEN + NAME.len();
+}
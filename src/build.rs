@@ -1,22 +1,52 @@
 use anyhow::{Context, Result, bail, ensure};
 use rustfix::diagnostics::Diagnostic;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{
+    borrow::Cow,
     collections::HashSet,
     ffi::OsStr,
     fmt::{Debug, Display},
-    path::PathBuf,
-    process::Command,
-    rc::Rc,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Child, Command, Output, Stdio},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
 };
 
-use crate::{EnvVar, Options, dylib_flag::RustFunction};
+use crate::{EnvVar, Options, dylib_flag::RustFunction, processor::CacheKey};
 
 #[derive(Debug, Clone)]
 pub struct Build {
-    inner: Rc<BuildInner>,
+    inner: Arc<BuildInner>,
 }
 
+/// How many of the runs of a build have to reproduce the issue for the build to count as reproducing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Accept {
+    #[default]
+    Any,
+    All,
+    Majority,
+}
+
+impl Accept {
+    /// Whether the build reproduces, or `None` if that depends on the runs that are still left.
+    fn decide(self, reproduced: usize, failed: usize, runs: usize) -> Option<bool> {
+        let done = reproduced + failed == runs;
+        match self {
+            Accept::Any if reproduced > 0 => Some(true),
+            Accept::All if failed > 0 => Some(false),
+            Accept::Majority if reproduced * 2 > runs => Some(true),
+            Accept::Majority if failed * 2 >= runs => Some(false),
+            Accept::Any | Accept::All | Accept::Majority if done => Some(reproduced > 0),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub enum Verify {
     Ice,
     Custom(RustFunction),
@@ -33,7 +63,7 @@ impl Debug for Verify {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct BuildInner {
     mode: BuildMode,
     lint_mode: BuildMode,
@@ -43,9 +73,18 @@ struct BuildInner {
     allow_color: bool,
     project_dir: Option<PathBuf>,
     extra_args: Vec<String>,
+    timeout: Option<Duration>,
+    /// Whether a build that timed out counts as reproducing the issue.
+    timeout_reproduces: bool,
+    runs: usize,
+    accept: Accept,
+    /// Everything about the build configuration that can change whether an issue reproduces.
+    fingerprint: String,
+    /// The path that the files are cached as, if they are a copy of the `--path`.
+    cache_path: Option<PathBuf>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 enum BuildMode {
     Cargo {
         cargo_path: PathBuf,
@@ -113,8 +152,10 @@ impl Build {
             Verify::Ice
         };
 
+        let fingerprint = fingerprint(options, &mode, &verify);
+
         Ok(Self {
-            inner: Rc::new(BuildInner {
+            inner: Arc::new(BuildInner {
                 mode,
                 lint_mode,
                 input_path: options.path.clone(),
@@ -123,10 +164,121 @@ impl Build {
                 allow_color: !options.no_color,
                 project_dir: options.project_dir.clone(),
                 extra_args,
+                timeout: options.timeout.map(Duration::from_secs),
+                timeout_reproduces: options.timeout_reproduces,
+                runs: options.runs,
+                accept: options.accept,
+                fingerprint,
+                cache_path: None,
             }),
         })
     }
 
+    /// Creates a build for a copy of the project at `root`, which lives in `copy`.
+    /// The copy gets its own target directory, so that builds in different copies don't block each other.
+    pub fn for_copy(&self, root: &Path, copy: &Path) -> Result<Self> {
+        let mut inner = BuildInner::clone(&self.inner);
+
+        let rebase = |path: &Path| -> Result<PathBuf> {
+            let path = path
+                .canonicalize()
+                .with_context(|| format!("canonicalizing {}", path.display()))?;
+            let relative = path.strip_prefix(root).with_context(|| {
+                format!(
+                    "{} is not inside of the project directory {}",
+                    path.display(),
+                    root.display()
+                )
+            })?;
+            Ok(copy.join(relative))
+        };
+
+        if let BuildMode::Rustc(_) = inner.mode {
+            inner.input_path = rebase(&inner.input_path)?;
+        }
+        // Scripts are still run from the original project, but in the directory of the copy.
+        for mode in [&mut inner.mode, &mut inner.lint_mode] {
+            if let BuildMode::Script(script) = mode
+                && script.is_relative()
+            {
+                *script = root.join(&*script);
+            }
+        }
+
+        inner.project_dir = Some(copy.to_owned());
+        inner.env.push(EnvVar {
+            key: "CARGO_TARGET_DIR".into(),
+            value: copy.join("target").to_string_lossy().into_owned(),
+        });
+
+        Ok(Self {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Makes the cache of a copy for `--output` match the one of the original, by using the fingerprint
+    /// and file paths of the original options.
+    pub(crate) fn share_cache_with(&mut self, original: &Options) {
+        let inner = Arc::make_mut(&mut self.inner);
+        let mode = match (&inner.mode, &original.script_path) {
+            (BuildMode::Script(_), Some(script)) => BuildMode::Script(script.clone()),
+            (mode, _) => mode.clone(),
+        };
+        inner.fingerprint = fingerprint(original, &mode, &inner.verify);
+        inner.cache_path = Some(original.path.clone());
+    }
+
+    /// The path that a file is cached as. For a copy, this is the path of the original file.
+    pub(crate) fn cache_path<'a>(&self, path: &'a Path) -> Cow<'a, Path> {
+        let Some(original) = &self.inner.cache_path else {
+            return Cow::Borrowed(path);
+        };
+        match path.strip_prefix(&self.inner.input_path) {
+            Ok(relative) if relative.as_os_str().is_empty() => Cow::Owned(original.clone()),
+            Ok(relative) => Cow::Owned(original.join(relative)),
+            Err(_) => Cow::Borrowed(path),
+        }
+    }
+
+    /// Identifies the configuration of the build, two builds of the same code with the same fingerprint
+    /// are expected to have the same result. Copies of the project share the fingerprint of the original.
+    pub fn fingerprint(&self) -> &str {
+        &self.inner.fingerprint
+    }
+
+    /// The result of a build that has been done before and whose result was cached.
+    pub fn cached_result(&self, reproduces_issue: bool) -> BuildResult {
+        BuildResult {
+            reproduces_issue,
+            no_verify: false,
+            cached: true,
+            timed_out: false,
+            runs: None,
+            output: String::new(),
+            allow_color: self.inner.allow_color,
+        }
+    }
+
+    fn timed_out_result(&self) -> BuildResult {
+        BuildResult {
+            reproduces_issue: self.inner.timeout_reproduces,
+            no_verify: false,
+            cached: false,
+            timed_out: true,
+            runs: None,
+            output: String::new(),
+            allow_color: self.inner.allow_color,
+        }
+    }
+
+    /// Runs the command like `Command::output`, but kills it after the timeout. Returns `None` if it timed out.
+    fn output(&self, cmd: &mut Command) -> std::io::Result<Option<Output>> {
+        match self.inner.timeout {
+            Some(timeout) => output_with_timeout(cmd, timeout),
+            None => cmd.output().map(Some),
+        }
+    }
+
     fn cmd(&self, name: impl AsRef<OsStr>) -> Command {
         let mut cmd = Command::new(name);
         if let Some(path) = &self.inner.project_dir {
@@ -135,13 +287,45 @@ impl Build {
         cmd
     }
 
+    /// Builds the code, as many times as configured with `--runs`.
     pub fn build(&self) -> Result<BuildResult> {
+        self.build_runs(self.inner.runs, true)
+    }
+
+    /// Builds the code `runs` times. With `stop_early`, it stops once it's clear whether enough runs reproduce the issue.
+    pub fn build_runs(&self, runs: usize, stop_early: bool) -> Result<BuildResult> {
+        let (mut reproduced, mut failed) = (0, 0);
+        loop {
+            let mut result = self.build_once()?;
+            if result.reproduces_issue() {
+                reproduced += 1;
+            } else {
+                failed += 1;
+            }
+
+            if runs <= 1 {
+                return Ok(result);
+            }
+            if let Some(reproduces_issue) = self.inner.accept.decide(reproduced, failed, runs)
+                && (stop_early || reproduced + failed == runs)
+            {
+                result.reproduces_issue = reproduces_issue;
+                result.runs = Some((reproduced, reproduced + failed));
+                return Ok(result);
+            }
+        }
+    }
+
+    fn build_once(&self) -> Result<BuildResult> {
         let inner = &self.inner;
 
         if let Verify::None = inner.verify {
             return Ok(BuildResult {
                 reproduces_issue: false,
                 no_verify: true,
+                cached: false,
+                timed_out: false,
+                runs: None,
                 output: String::new(),
                 allow_color: inner.allow_color,
             });
@@ -168,7 +352,9 @@ impl Build {
                     cmd.env(&env.key, &env.value);
                 }
 
-                let outputs = cmd.output().context("spawning rustc process")?;
+                let Some(outputs) = self.output(&mut cmd).context("spawning rustc process")? else {
+                    return Ok(self.timed_out_result());
+                };
 
                 let output = String::from_utf8(outputs.stderr)?;
 
@@ -194,7 +380,9 @@ impl Build {
                     cmd.env(&env.key, &env.value);
                 }
 
-                let outputs = cmd.output().context("spawning rustc process")?;
+                let Some(outputs) = self.output(&mut cmd).context("spawning rustc process")? else {
+                    return Ok(self.timed_out_result());
+                };
 
                 let output = String::from_utf8(outputs.stderr)?;
 
@@ -214,9 +402,12 @@ impl Build {
                     cmd.env(&env.key, &env.value);
                 }
 
-                let outputs = cmd
-                    .output()
-                    .with_context(|| format!("spawning script: `{cmd:?}`"))?;
+                let Some(outputs) = self
+                    .output(&mut cmd)
+                    .with_context(|| format!("spawning script: `{cmd:?}`"))?
+                else {
+                    return Ok(self.timed_out_result());
+                };
 
                 let output = String::from_utf8(outputs.stderr)?;
 
@@ -233,6 +424,9 @@ impl Build {
         Ok(BuildResult {
             reproduces_issue,
             no_verify: false,
+            cached: false,
+            timed_out: false,
+            runs: None,
             output,
             allow_color: inner.allow_color,
         })
@@ -279,7 +473,9 @@ impl Build {
                     cmd.env(&env.key, &env.value);
                 }
 
-                let cmd_output = cmd.output()?;
+                let Some(cmd_output) = self.output(&mut cmd)? else {
+                    return Ok(lints_timed_out());
+                };
                 let output = String::from_utf8(cmd_output.stdout)?;
 
                 grab_cargo_diags(&output)?
@@ -293,8 +489,10 @@ impl Build {
                     cmd.env(&env.key, &env.value);
                 }
 
-                let output = cmd.output()?.stderr;
-                let output = String::from_utf8(output)?;
+                let Some(output) = self.output(&mut cmd)? else {
+                    return Ok(lints_timed_out());
+                };
+                let output = String::from_utf8(output.stderr)?;
 
                 grab_rustc_diags(&output)?
             }
@@ -309,9 +507,12 @@ impl Build {
 
                 cmd.env("MINIMIZE_LINTS", "1");
 
-                let outputs = cmd
-                    .output()
-                    .with_context(|| format!("spawning script: `{cmd:?}`"))?;
+                let Some(outputs) = self
+                    .output(&mut cmd)
+                    .with_context(|| format!("spawning script: `{cmd:?}`"))?
+                else {
+                    return Ok(lints_timed_out());
+                };
 
                 let stderr = String::from_utf8(outputs.stderr)?;
                 let stdout = String::from_utf8(outputs.stdout)?;
@@ -339,10 +540,199 @@ impl Build {
     }
 }
 
+/// Without lints, nothing is deleted. That's better than failing, as code that hangs the compiler can still
+/// be minimized by the other passes.
+fn lints_timed_out() -> (Vec<Diagnostic>, Vec<rustfix::Suggestion>) {
+    warn!("Getting lints timed out, continuing without them");
+    (Vec::new(), Vec::new())
+}
+
+fn output_with_timeout(cmd: &mut Command, timeout: Duration) -> std::io::Result<Option<Output>> {
+    // Put the process into its own process group, so that we can kill everything it spawns.
+    // This also takes it out of the terminal's foreground group, so it doesn't get the SIGINT of a Ctrl-C.
+    // The minimizer notices the Ctrl-C itself and stops after this build.
+    #[cfg(unix)]
+    std::os::unix::process::CommandExt::process_group(cmd, 0);
+
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // The pipes have to be drained while the process runs, or it blocks once they are full.
+    let read = |mut pipe: Box<dyn Read + Send>| {
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            pipe.read_to_end(&mut buf).map(|_| buf)
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take().unwrap()));
+    let stderr = read(Box::new(child.stderr.take().unwrap()));
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break Some(status);
+        }
+        if Instant::now() >= deadline {
+            kill_process_tree(&mut child);
+            child.wait()?;
+            break None;
+        }
+        thread::sleep(Duration::from_millis(20));
+    };
+
+    let stdout = stdout.join().expect("reading thread panicked")?;
+    let stderr = stderr.join().expect("reading thread panicked")?;
+
+    Ok(status.map(|status| Output {
+        status,
+        stdout,
+        stderr,
+    }))
+}
+
+fn kill_process_tree(child: &mut Child) {
+    #[cfg(unix)]
+    {
+        // The child leads its own process group (see `output_with_timeout`), so this kills it and all its descendants.
+        // SAFETY: `kill` has no memory safety requirements.
+        let result = unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+        if result == 0 {
+            return;
+        }
+    }
+    child.kill().ok();
+}
+
 fn extra_cargoflags(cargo: &mut Command) {
     cargo.arg("--offline");
 }
 
+/// Everything about the build configuration that can change whether an issue reproduces.
+fn fingerprint(options: &Options, mode: &BuildMode, verify: &Verify) -> String {
+    let extra_args = options
+        .extra_args
+        .as_deref()
+        .map(split_args)
+        .unwrap_or_default();
+    let mut fingerprint = format!(
+        "{mode:?} {:?} {:?} {:?} {extra_args:?} {}",
+        options.path, options.env, options.project_dir, !options.no_color
+    );
+    match verify {
+        Verify::Ice => fingerprint.push_str(" verify-ice"),
+        Verify::Custom(func) => {
+            fingerprint.push_str(" verify-fn ");
+            fingerprint.push_str(func.source());
+        }
+        Verify::None => fingerprint.push_str(" no-verify"),
+    }
+    if options.runs > 1 {
+        fingerprint.push_str(&format!(" runs {} {:?}", options.runs, options.accept));
+    }
+    if let Some(timeout) = options.timeout {
+        fingerprint.push_str(&format!(
+            " timeout {timeout} {}",
+            options.timeout_reproduces
+        ));
+    }
+    // Scripts may be looked up in the PATH, so we can't always read them.
+    if let BuildMode::Script(script) = mode
+        && let Ok(content) = std::fs::read(script)
+    {
+        fingerprint.push(' ');
+        fingerprint.push_str(&String::from_utf8_lossy(&content));
+    }
+    fingerprint.push(' ');
+    fingerprint.push_str(&environment(options));
+    fingerprint
+}
+
+/// What the build depends on besides the files that are minimized: the toolchain, the manifests and
+/// the lockfile, and all other sources of the project. They are hashed, as they can get large.
+fn environment(options: &Options) -> String {
+    let dir = options.project_dir.as_deref().unwrap_or(Path::new("."));
+
+    let mut versions = String::new();
+    // The toolchain can be picked per directory with `rust-toolchain.toml` or with the environment.
+    for (tool, arg) in [("rustc", "-vV"), ("cargo", "-V")] {
+        let output = Command::new(tool)
+            .arg(arg)
+            .current_dir(dir)
+            .envs(options.env.iter().map(|env| (&env.key, &env.value)))
+            .output();
+        match output {
+            Ok(output) if output.status.success() => {
+                versions.push_str(&String::from_utf8_lossy(&output.stdout));
+            }
+            _ => warn!(
+                "Failed to get the version of {tool}, the build cache won't notice when it changes"
+            ),
+        }
+    }
+
+    // With `--rustc`, only the file itself is built, not a project.
+    let files = if options.rustc {
+        Vec::new()
+    } else {
+        project_files(options, dir)
+    };
+
+    let contents = files
+        .into_iter()
+        .filter_map(|path| {
+            let content = std::fs::read(&path).ok()?;
+            Some((path, String::from_utf8_lossy(&content).into_owned()))
+        })
+        .collect::<Vec<_>>();
+    let sources = CacheKey::new(
+        "",
+        contents
+            .iter()
+            .map(|(path, content)| (path, content.as_str())),
+    );
+    format!("{versions}{sources}")
+}
+
+/// The manifests and sources of the project that are not minimized, and the lockfile.
+fn project_files(options: &Options, dir: &Path) -> Vec<PathBuf> {
+    let minimized = options.path.canonicalize().ok();
+    let walk = walkdir::WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| {
+            let name = entry.file_name().to_string_lossy();
+            entry.depth() == 0 || !(name.starts_with('.') || name == "target")
+        })
+        .filter_map(Result::ok);
+
+    let mut files = Vec::new();
+    for entry in walk {
+        let path = entry.path();
+        let relevant =
+            entry.file_name() == "Cargo.toml" || path.extension().is_some_and(|ext| ext == "rs");
+        let is_minimized = minimized.as_ref().is_some_and(|minimized| {
+            path.canonicalize()
+                .is_ok_and(|path| path.starts_with(minimized))
+        });
+        if entry.file_type().is_file() && relevant && !is_minimized {
+            files.push(path.to_owned());
+        }
+    }
+    // In a workspace, the lockfile is next to the workspace manifest.
+    if let Ok(dir) = dir.canonicalize()
+        && let Some(lockfile) = dir
+            .ancestors()
+            .map(|ancestor| ancestor.join("Cargo.lock"))
+            .find(|lockfile| lockfile.is_file())
+    {
+        files.push(lockfile);
+    }
+    files
+}
+
 pub fn rustup_which(tool: &str) -> Result<PathBuf> {
     let output = Command::new("rustup")
         .arg("which")
@@ -359,11 +749,20 @@ pub fn rustup_which(tool: &str) -> Result<PathBuf> {
         .into())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct BuildResult {
+    // The events contain the result of `reproduces_issue()` instead.
+    #[serde(skip)]
     reproduces_issue: bool,
+    #[serde(skip)]
     no_verify: bool,
+    cached: bool,
+    timed_out: bool,
+    /// How many runs reproduced the issue out of how many, if there was more than one.
+    runs: Option<(usize, usize)>,
+    #[serde(skip)]
     output: String,
+    #[serde(skip)]
     allow_color: bool,
 }
 
@@ -398,7 +797,18 @@ impl Display for BuildResult {
                 (false, true) => write!(f, "{}", "yes (no-verify)".green()),
                 (false, false) => write!(f, "{}", "no".red()),
             },
+        }?;
+
+        if let Some((reproduced, runs)) = self.runs {
+            write!(f, " ({reproduced}/{runs} runs)")?;
+        }
+        if self.timed_out {
+            f.write_str(" (timed out)")?;
         }
+        if self.cached {
+            f.write_str(" (cached)")?;
+        }
+        Ok(())
     }
 }
 
@@ -439,7 +849,72 @@ fn read_script_output<'a>(stdout: &'a str, stderr: &'a str) -> (&'a str, LintMod
 mod tests {
     use crate::build::LintMode;
 
-    use super::read_script_output;
+    use super::{Accept, read_script_output};
+
+    #[cfg(unix)]
+    #[test]
+    fn timeout_kills_the_process_group() {
+        use std::{
+            process::Command,
+            time::{Duration, Instant},
+        };
+
+        let start = Instant::now();
+        // The background `sleep` keeps the output pipes open, so this only returns in time if it is killed too.
+        let output = super::output_with_timeout(
+            Command::new("sh").args(["-c", "sleep 30 & sleep 30"]),
+            Duration::from_secs(1),
+        )
+        .unwrap();
+        assert!(output.is_none());
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn project_files_leave_out_minimized_files() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "Cargo.toml",
+            "Cargo.lock",
+            "build.rs",
+            "src/main.rs",
+            "target/debug/build/out.rs",
+        ] {
+            let path = dir.path().join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let options = crate::Options {
+            path: dir.path().join("src"),
+            ..Default::default()
+        };
+
+        let files = super::project_files(&options, dir.path());
+        let root = dir.path().canonicalize().unwrap();
+        let files = files
+            .iter()
+            .map(|path| {
+                path.strip_prefix(dir.path())
+                    .or_else(|_| path.strip_prefix(&root))
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(files, ["Cargo.toml", "build.rs", "Cargo.lock"]);
+    }
+
+    #[test]
+    fn accept_stops_early() {
+        assert_eq!(Accept::Any.decide(1, 0, 3), Some(true));
+        assert_eq!(Accept::Any.decide(0, 2, 3), None);
+        assert_eq!(Accept::Any.decide(0, 3, 3), Some(false));
+        assert_eq!(Accept::All.decide(2, 0, 3), None);
+        assert_eq!(Accept::All.decide(2, 1, 3), Some(false));
+        assert_eq!(Accept::All.decide(3, 0, 3), Some(true));
+        assert_eq!(Accept::Majority.decide(1, 1, 3), None);
+        assert_eq!(Accept::Majority.decide(2, 0, 3), Some(true));
+        assert_eq!(Accept::Majority.decide(1, 1, 4), None);
+        assert_eq!(Accept::Majority.decide(1, 2, 4), Some(false));
+    }
 
     #[test]
     fn script_output_default() {
@@ -0,0 +1,471 @@
+//! Handles the `minimize.toml` file, which can set all options and the pipeline of passes.
+//! Options from the command line win over the file.
+
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Accept, Bisection, EnvVar, Formatter, MessageFormat, Options, SizeMetric, passes,
+    processor::DEAD_CODE_PASS_NAME,
+};
+
+pub(crate) const FILE_NAME: &str = "minimize.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    #[serde(default)]
+    options: ConfigOptions,
+    pipeline: Option<Vec<Step>>,
+}
+
+/// The same options as on the command line. Relative paths are relative to the config file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct ConfigOptions {
+    extra_args: Option<String>,
+    cargo_subcmd: Option<String>,
+    cargo_subcmd_lints: Option<String>,
+    no_color: Option<bool>,
+    rustc: Option<bool>,
+    no_verify: Option<bool>,
+    verify_fn: Option<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    project_dir: Option<PathBuf>,
+    path: Option<PathBuf>,
+    passes: Option<String>,
+    script_path: Option<PathBuf>,
+    script_path_lints: Option<PathBuf>,
+    #[serde(default)]
+    ignore_file: Vec<PathBuf>,
+    no_delete_functions: Option<bool>,
+    bisect_delete_imports: Option<bool>,
+    jobs: Option<usize>,
+    cache_file: Option<PathBuf>,
+    state_file: Option<PathBuf>,
+    resume: Option<bool>,
+    fixpoint: Option<bool>,
+    max_rounds: Option<usize>,
+    timeout: Option<u64>,
+    timeout_reproduces: Option<bool>,
+    runs: Option<usize>,
+    accept: Option<Accept>,
+    final_runs: Option<usize>,
+    bisection: Option<Bisection>,
+    output: Option<PathBuf>,
+    history_branch: Option<String>,
+    allow_dirty: Option<bool>,
+    message_format: Option<MessageFormat>,
+    dry_run: Option<bool>,
+    interactive: Option<bool>,
+    emit_patches: Option<PathBuf>,
+    require_shrink: Option<bool>,
+    size_metric: Option<SizeMetric>,
+    preserve_formatting: Option<bool>,
+    formatter: Option<Formatter>,
+}
+
+/// A step of the pipeline. Either the name of a pass, a pass with options, or a group of steps
+/// that is repeated until it doesn't make any changes anymore.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Step {
+    Name(String),
+    Pass(PassStep),
+    Group(GroupStep),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct PassStep {
+    pub(crate) pass: String,
+    no_delete_functions: Option<bool>,
+    bisect_delete_imports: Option<bool>,
+    bisection: Option<Bisection>,
+}
+
+/// Options that can be set for a single pass, overriding the global ones.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PassOptions {
+    pub(crate) no_delete_functions: Option<bool>,
+    pub(crate) bisect_delete_imports: Option<bool>,
+    pub(crate) bisection: Option<Bisection>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(crate) struct GroupStep {
+    pub(crate) group: Vec<Step>,
+    /// The group stops after this many rounds, even if it still makes changes.
+    pub(crate) max_rounds: Option<usize>,
+}
+
+impl PassStep {
+    pub(crate) fn options(&self) -> PassOptions {
+        PassOptions {
+            no_delete_functions: self.no_delete_functions,
+            bisect_delete_imports: self.bisect_delete_imports,
+            bisection: self.bisection,
+        }
+    }
+}
+
+impl PassOptions {
+    /// Sets the options and returns the previous ones, so that they can be restored by applying those.
+    pub(crate) fn apply(&self, options: &mut Options) -> PassOptions {
+        let previous = PassOptions {
+            no_delete_functions: Some(options.no_delete_functions),
+            bisect_delete_imports: Some(options.bisect_delete_imports),
+            bisection: Some(options.bisection),
+        };
+        if let Some(no_delete_functions) = self.no_delete_functions {
+            options.no_delete_functions = no_delete_functions;
+        }
+        if let Some(bisect_delete_imports) = self.bisect_delete_imports {
+            options.bisect_delete_imports = bisect_delete_imports;
+        }
+        if let Some(bisection) = self.bisection {
+            options.bisection = bisection;
+        }
+        previous
+    }
+}
+
+impl Config {
+    /// Loads the file from `--config`, or `minimize.toml` in the project directory if it exists.
+    pub(crate) fn find(options: &Options) -> Result<Option<(PathBuf, Self)>> {
+        let path = match &options.config {
+            Some(path) => path.clone(),
+            None => {
+                let path = options
+                    .project_dir
+                    .as_deref()
+                    .unwrap_or(Path::new("."))
+                    .join(FILE_NAME);
+                if !path.try_exists()? {
+                    return Ok(None);
+                }
+                path
+            }
+        };
+
+        let content = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading config file {}", path.display()))?;
+        let config = Self::parse(&content)
+            .with_context(|| format!("parsing config file {}", path.display()))?;
+        Ok(Some((path, config)))
+    }
+
+    fn parse(content: &str) -> Result<Self> {
+        let config = toml::from_str::<Config>(content)?;
+        if let Some(pipeline) = &config.pipeline {
+            validate_steps(pipeline)?;
+        }
+        Ok(config)
+    }
+
+    pub(crate) fn pipeline(&self) -> Option<&[Step]> {
+        self.pipeline.as_deref()
+    }
+
+    /// Fills in all options that were not set on the command line. `dir` is the directory of the config file.
+    pub(crate) fn apply(&self, options: &mut Options, dir: &Path) -> Result<()> {
+        let config = &self.options;
+        let path = |path: &PathBuf| dir.join(path);
+
+        fill(&mut options.extra_args, config.extra_args.clone());
+        fill_value(
+            options.is_explicit("cargo_subcmd"),
+            &mut options.cargo_subcmd,
+            "build".into(),
+            config.cargo_subcmd.clone(),
+        );
+        fill(
+            &mut options.cargo_subcmd_lints,
+            config.cargo_subcmd_lints.clone(),
+        );
+        fill_flag(&mut options.no_color, config.no_color);
+        fill_flag(&mut options.rustc, config.rustc);
+        fill_flag(&mut options.no_verify, config.no_verify);
+        if options.verify_fn.is_none()
+            && let Some(verify_fn) = &config.verify_fn
+        {
+            options.verify_fn = Some(verify_fn.parse().context("verify-fn")?);
+        }
+        // Variables from the command line come last, so they win.
+        let mut env = config
+            .env
+            .iter()
+            .map(|(key, value)| EnvVar {
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect::<Vec<_>>();
+        env.append(&mut options.env);
+        options.env = env;
+        fill(
+            &mut options.project_dir,
+            config.project_dir.as_ref().map(path),
+        );
+        fill_value(
+            options.is_explicit("path"),
+            &mut options.path,
+            PathBuf::from("src"),
+            config.path.as_ref().map(path),
+        );
+        if options.passes.is_none()
+            && let Some(passes) = &config.passes
+        {
+            options.passes = Some(passes.parse().map_err(anyhow::Error::msg)?);
+        }
+        fill(
+            &mut options.script_path,
+            config.script_path.as_ref().map(path),
+        );
+        fill(
+            &mut options.script_path_lints,
+            config.script_path_lints.as_ref().map(path),
+        );
+        options
+            .ignore_file
+            .extend(config.ignore_file.iter().map(path));
+        fill_flag(&mut options.no_delete_functions, config.no_delete_functions);
+        fill_flag(
+            &mut options.bisect_delete_imports,
+            config.bisect_delete_imports,
+        );
+        fill_value(
+            options.is_explicit("jobs"),
+            &mut options.jobs,
+            1,
+            config.jobs,
+        );
+        fill(
+            &mut options.cache_file,
+            config.cache_file.as_ref().map(path),
+        );
+        fill(
+            &mut options.state_file,
+            config.state_file.as_ref().map(path),
+        );
+        fill_flag(&mut options.resume, config.resume);
+        fill_flag(&mut options.fixpoint, config.fixpoint);
+        fill(&mut options.max_rounds, config.max_rounds);
+        fill(&mut options.timeout, config.timeout);
+        fill_flag(&mut options.timeout_reproduces, config.timeout_reproduces);
+        fill_value(
+            options.is_explicit("runs"),
+            &mut options.runs,
+            1,
+            config.runs,
+        );
+        fill_value(
+            options.is_explicit("accept"),
+            &mut options.accept,
+            Accept::default(),
+            config.accept,
+        );
+        fill_value(
+            options.is_explicit("final_runs"),
+            &mut options.final_runs,
+            1,
+            config.final_runs,
+        );
+        fill_value(
+            options.is_explicit("bisection"),
+            &mut options.bisection,
+            Bisection::default(),
+            config.bisection,
+        );
+
+        fill(&mut options.output, config.output.as_ref().map(path));
+        fill(&mut options.history_branch, config.history_branch.clone());
+        fill_flag(&mut options.allow_dirty, config.allow_dirty);
+        fill_value(
+            options.is_explicit("message_format"),
+            &mut options.message_format,
+            MessageFormat::default(),
+            config.message_format,
+        );
+        fill_flag(&mut options.dry_run, config.dry_run);
+        fill_flag(&mut options.interactive, config.interactive);
+        fill(
+            &mut options.emit_patches,
+            config.emit_patches.as_ref().map(path),
+        );
+        fill_flag(&mut options.require_shrink, config.require_shrink);
+        fill_value(
+            options.is_explicit("size_metric"),
+            &mut options.size_metric,
+            SizeMetric::default(),
+            config.size_metric,
+        );
+        fill_flag(&mut options.preserve_formatting, config.preserve_formatting);
+        fill_value(
+            options.is_explicit("formatter"),
+            &mut options.formatter,
+            Formatter::default(),
+            config.formatter,
+        );
+
+        if options.resume && options.state_file.is_none() {
+            bail!("resume requires a state file");
+        }
+        if options.output.is_some() && options.state_file.is_some() {
+            bail!("output can't be used together with a state file");
+        }
+        if options.output.is_some() && options.history_branch.is_some() {
+            bail!("output can't be used together with a history branch");
+        }
+
+        Ok(())
+    }
+}
+
+/// Sets an option that is unset on the command line.
+fn fill<T>(option: &mut Option<T>, value: Option<T>) {
+    if option.is_none() {
+        *option = value;
+    }
+}
+
+/// Sets an option that has a default value instead of being optional, unless it was given on the command
+/// line or was changed from its default otherwise.
+fn fill_value<T: PartialEq>(explicit: bool, option: &mut T, default: T, value: Option<T>) {
+    if !explicit
+        && *option == default
+        && let Some(value) = value
+    {
+        *option = value;
+    }
+}
+
+/// Sets a flag that wasn't passed on the command line.
+fn fill_flag(flag: &mut bool, value: Option<bool>) {
+    if !*flag && let Some(value) = value {
+        *flag = value;
+    }
+}
+
+/// The pipeline that is used when the config file doesn't have one.
+pub(crate) fn default_pipeline() -> Vec<Step> {
+    [
+        "everybody-loops",
+        "split-use",
+        "field-deleter",
+        "pattern-simplifier",
+        "closure-simplifier",
+        "const-stubber",
+        "privatize",
+        DEAD_CODE_PASS_NAME,
+        "item-deleter",
+    ]
+    .into_iter()
+    .map(|name| Step::Name(name.to_owned()))
+    .collect()
+}
+
+fn validate_steps(steps: &[Step]) -> Result<()> {
+    for step in steps {
+        match step {
+            Step::Name(name) | Step::Pass(PassStep { pass: name, .. }) => {
+                if name != DEAD_CODE_PASS_NAME && passes::by_name(name).is_none() {
+                    bail!("unknown pass `{name}` in pipeline");
+                }
+            }
+            Step::Group(group) => validate_steps(&group.group)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{Config, Step};
+    use crate::{Bisection, Cargo, Options};
+
+    #[test]
+    fn pipeline() {
+        let config = Config::parse(
+            r#"
+            pipeline = [
+                "everybody-loops",
+                { pass = "item-deleter", no-delete-functions = true, bisection = "ddmin" },
+                { group = ["field-deleter", "delete-unused-functions"], max-rounds = 2 },
+            ]
+            "#,
+        )
+        .unwrap();
+
+        let pipeline = config.pipeline().unwrap();
+        assert!(matches!(&pipeline[0], Step::Name(name) if name == "everybody-loops"));
+        assert!(matches!(
+            &pipeline[1],
+            Step::Pass(pass) if pass.pass == "item-deleter" && pass.no_delete_functions == Some(true)
+        ));
+        assert!(matches!(
+            &pipeline[2],
+            Step::Group(group) if group.group.len() == 2 && group.max_rounds == Some(2)
+        ));
+
+        assert!(Config::parse(r#"pipeline = ["no-such-pass"]"#).is_err());
+    }
+
+    #[test]
+    fn command_line_wins() {
+        let config = Config::parse(
+            r#"
+            [options]
+            cargo-subcmd = "check"
+            jobs = 4
+            script-path = "check.sh"
+            env = { A = "config" }
+            "#,
+        )
+        .unwrap();
+
+        let mut options = Options {
+            path: "src".into(),
+            jobs: 2,
+            env: vec!["A=cli".parse().unwrap()],
+            ..Options::default()
+        };
+        config.apply(&mut options, Path::new("/repro")).unwrap();
+
+        assert_eq!(options.cargo_subcmd, "check");
+        assert_eq!(options.jobs, 2);
+        assert_eq!(
+            options.script_path.as_deref(),
+            Some(Path::new("/repro/check.sh"))
+        );
+        assert_eq!(options.env.last().unwrap().value, "cli");
+    }
+
+    #[test]
+    fn command_line_wins_with_default_value() {
+        let config = Config::parse(
+            r#"
+            [options]
+            bisection = "ddmin"
+            jobs = 4
+            "#,
+        )
+        .unwrap();
+
+        let matches = <Cargo as clap::CommandFactory>::command()
+            .try_get_matches_from(["cargo", "minimize", "--bisection", "halving"])
+            .unwrap();
+        let Cargo::Minimize(mut options) = Cargo::from_matches(&matches).unwrap();
+        config.apply(&mut options, Path::new("/repro")).unwrap();
+
+        assert_eq!(options.bisection, Bisection::Halving);
+        assert_eq!(options.jobs, 4);
+    }
+}
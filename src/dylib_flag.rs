@@ -19,6 +19,8 @@ type CheckerCFn = unsafe extern "C" fn(*const RawOutput) -> bool;
 #[derive(Clone, Copy)]
 pub struct RustFunction {
     func: CheckerCFn,
+    /// The closure that the function was compiled from. The dylib is never unloaded, so neither is this.
+    source: &'static str,
 }
 
 impl FromStr for RustFunction {
@@ -81,7 +83,7 @@ fn wrap_func_body(func: &str) -> Result<String> {
         }
     };
 
-    crate::formatting::format(syn_file)
+    crate::formatting::format(syn_file, crate::formatting::Style::default())
 }
 
 impl RustFunction {
@@ -121,7 +123,14 @@ impl RustFunction {
                 .context("failed to find entrypoint symbol")?
         };
 
-        Ok(Self { func: *func })
+        Ok(Self {
+            func: *func,
+            source: body.to_owned().leak(),
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        self.source
     }
 
     pub fn call(&self, output: &str, code: Option<i32>) -> bool {
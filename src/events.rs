@@ -0,0 +1,137 @@
+//! Handles `--message-format=json`, which writes what happens as newline-delimited JSON to stdout.
+
+use std::{io::Write, path::Path, time::Duration};
+
+use serde::{Serialize, Serializer};
+
+use crate::{Summary, build::BuildResult};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MessageFormat {
+    /// Only log for humans, on stderr.
+    #[default]
+    Human,
+    /// Additionally write one JSON object per line to stdout for every event.
+    Json,
+}
+
+/// Something that happened during the run. The kind is in the `event` field.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub(crate) enum Event<'a> {
+    PassStart {
+        pass: &'a str,
+    },
+    PassFinish {
+        pass: &'a str,
+        #[serde(rename = "duration_secs", serialize_with = "secs")]
+        duration: Duration,
+    },
+    /// The minimization sites that a pass found in a file, for `--dry-run`.
+    Candidates {
+        pass: &'a str,
+        file: &'a Path,
+        sites: &'a [String],
+    },
+    /// A set of minimization sites is about to be built.
+    Batch {
+        pass: &'a str,
+        file: &'a Path,
+        sites: &'a [String],
+    },
+    Build {
+        /// `None` for the builds before the passes.
+        pass: Option<&'a str>,
+        file: Option<&'a Path>,
+        reproduces: bool,
+        #[serde(flatten)]
+        result: &'a BuildResult,
+    },
+    Commit {
+        pass: &'a str,
+        file: &'a Path,
+        sites: &'a [String],
+    },
+    Rollback {
+        pass: &'a str,
+        file: &'a Path,
+        sites: &'a [String],
+    },
+    Summary {
+        cancelled: bool,
+        #[serde(flatten)]
+        summary: &'a Summary,
+    },
+}
+
+impl<'a> Event<'a> {
+    pub(crate) fn build(
+        pass: Option<&'a str>,
+        file: Option<&'a Path>,
+        result: &'a BuildResult,
+    ) -> Self {
+        Event::Build {
+            pass,
+            file,
+            reproduces: result.reproduces_issue(),
+            result,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Events {
+    format: MessageFormat,
+}
+
+impl Events {
+    pub(crate) fn new(format: MessageFormat) -> Self {
+        Self { format }
+    }
+
+    pub(crate) fn emit(&self, event: Event<'_>) {
+        if self.format != MessageFormat::Json {
+            return;
+        }
+
+        let json = serde_json::to_string(&event).expect("events can always be serialized");
+        let mut stdout = std::io::stdout().lock();
+        // If nobody is listening anymore, that's not a reason to stop minimizing.
+        if let Err(err) = writeln!(stdout, "{json}").and_then(|()| stdout.flush()) {
+            debug!("Failed to write event: {err}");
+        }
+    }
+}
+
+pub(crate) fn secs<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use super::Event;
+
+    #[test]
+    fn events_are_tagged() {
+        let json = |event| serde_json::to_string(&event).unwrap();
+
+        assert_eq!(
+            json(Event::PassFinish {
+                pass: "privatize",
+                duration: Duration::from_millis(1500),
+            }),
+            r#"{"event":"pass-finish","pass":"privatize","duration_secs":1.5}"#
+        );
+        assert_eq!(
+            json(Event::Rollback {
+                pass: "item-deleter",
+                file: Path::new("src/main.rs"),
+                sites: &["a::b".to_owned()],
+            }),
+            r#"{"event":"rollback","pass":"item-deleter","file":"src/main.rs","sites":["a::b"]}"#
+        );
+    }
+}
@@ -1,11 +1,478 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    io::Write,
+    ops::Range,
+    path::Path,
+    process::{Command, Stdio},
+};
 
+use anyhow::{Context, ensure};
 use genemichaels::FormatConfig;
+use quote::ToTokens;
+use similar::{Algorithm, DiffOp};
+use syn::{AttrStyle, Item, spanned::Spanned};
 
-pub fn format(file: syn::File) -> anyhow::Result<String> {
-    Ok(
-        genemichaels::format_ast(file, &FormatConfig::default(), HashMap::new())
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Formatter {
+    #[default]
+    Genemichaels,
+    /// The `rustfmt` of the toolchain, as found by `rustup which`.
+    Rustfmt,
+    Prettyplease,
+}
+
+impl Formatter {
+    fn name(self) -> &'static str {
+        match self {
+            Formatter::Genemichaels => "genemichaels",
+            Formatter::Rustfmt => "rustfmt",
+            Formatter::Prettyplease => "prettyplease",
+        }
+    }
+
+    /// The formatters to try in order. prettyplease can print everything that syn can parse,
+    /// so it is the last resort.
+    fn with_fallbacks(self) -> impl Iterator<Item = Formatter> {
+        std::iter::once(self).chain(
+            [
+                Formatter::Genemichaels,
+                Formatter::Rustfmt,
+                Formatter::Prettyplease,
+            ]
+            .into_iter()
+            .filter(move |&other| other != self),
+        )
+    }
+
+    fn format_once(self, file: &syn::File, edition: Option<u16>) -> anyhow::Result<String> {
+        match self {
+            Formatter::Genemichaels => Ok(genemichaels::format_ast(
+                file.clone(),
+                &FormatConfig::default(),
+                HashMap::new(),
+            )
             .map_err(|e| anyhow::format_err!("formatting source file: {:?}", e))?
-            .rendered,
-    )
+            .rendered),
+            Formatter::Rustfmt => rustfmt(file, edition),
+            Formatter::Prettyplease => Ok(prettyplease::unparse(file)),
+        }
+    }
+}
+
+/// How the files of a crate are formatted.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Style {
+    pub(crate) formatter: Formatter,
+    /// The edition of the crate, which rustfmt needs to parse the code like the compiler does.
+    pub(crate) edition: Option<u16>,
+}
+
+impl Style {
+    /// The style for a file of the crate that `path` belongs to.
+    pub(crate) fn for_file(formatter: Formatter, path: &Path) -> Self {
+        Self {
+            formatter,
+            edition: edition(path),
+        }
+    }
+}
+
+/// The edition from the `Cargo.toml` of the crate that `path` belongs to, `None` if there is no manifest.
+fn edition(path: &Path) -> Option<u16> {
+    let path = path.canonicalize().ok()?;
+    let mut manifests = path
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join("Cargo.toml"))
+        .filter(|manifest| manifest.is_file())
+        .filter_map(|manifest| {
+            toml::from_str::<toml::Table>(&std::fs::read_to_string(manifest).ok()?).ok()
+        });
+    let manifest = manifests.next()?;
+    match manifest.get("package")?.get("edition") {
+        None => Some(2015),
+        Some(toml::Value::String(edition)) => edition.parse().ok(),
+        // `edition.workspace = true` inherits the edition from the workspace.
+        Some(_) => manifests.find_map(|manifest| {
+            manifest
+                .get("workspace")?
+                .get("package")?
+                .get("edition")?
+                .as_str()?
+                .parse()
+                .ok()
+        }),
+    }
+}
+
+/// Formats the file. If the formatter fails, the other formatters are tried instead.
+pub(crate) fn format(file: syn::File, style: Style) -> anyhow::Result<String> {
+    let mut last_err = None;
+    for formatter in style.formatter.with_fallbacks() {
+        match formatter.format_once(&file, style.edition) {
+            Ok(rendered) => return Ok(rendered),
+            Err(err) => {
+                warn!("Formatting with {} failed: {err:#}", formatter.name());
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(last_err.expect("there is always a formatter to try"))
+}
+
+/// rustfmt can't format an AST, so the output of prettyplease is formatted instead.
+/// Without an edition, rustfmt uses the one from a `rustfmt.toml`.
+fn rustfmt(file: &syn::File, edition: Option<u16>) -> anyhow::Result<String> {
+    let rustfmt = crate::rustup_which("rustfmt")?;
+    let mut cmd = Command::new(rustfmt);
+    if let Some(edition) = edition {
+        cmd.args(["--edition", &edition.to_string()]);
+    }
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("spawning rustfmt")?;
+    // rustfmt reads all of stdin before writing anything, so this can't block.
+    child
+        .stdin
+        .take()
+        .expect("stdin is piped")
+        .write_all(prettyplease::unparse(file).as_bytes())
+        .context("writing to rustfmt")?;
+    let output = child.wait_with_output().context("waiting for rustfmt")?;
+    ensure!(
+        output.status.success(),
+        "rustfmt failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    String::from_utf8(output.stdout).context("rustfmt returned invalid utf8")
+}
+
+/// Renders `new` for `--preserve-formatting`. Only the items that differ from `old` are rendered, and
+/// spliced into `source`, which `old` must have been parsed from. Everything else is kept as it was,
+/// including the comments around and inside unchanged items.
+pub(crate) fn splice(
+    source: &str,
+    old: &syn::File,
+    new: &syn::File,
+    style: Style,
+) -> anyhow::Result<String> {
+    let attrs = |file: &syn::File| file.attrs.iter().map(key).collect::<Vec<_>>();
+    if new.shebang != old.shebang || attrs(old) != attrs(new) {
+        return format(new.clone(), style);
+    }
+
+    let mut start = end_of_attrs(&old.attrs, 0);
+    if old.shebang.is_some() {
+        start = start.max(source.find('\n').map_or(source.len(), |end| end + 1));
+    }
+    if start > source.len() || !source.is_char_boundary(start) {
+        return format(new.clone(), style);
+    }
+
+    match splice_items(
+        source,
+        &old.items,
+        &new.items,
+        start..source.len(),
+        0,
+        style,
+    )? {
+        Some(items) => Ok(format!("{}{items}", &source[..start])),
+        // The spans don't belong to the source, so there is nothing to keep.
+        None => format(new.clone(), style),
+    }
+}
+
+/// Splices the items in `region` of the source. Returns `None` if the spans of the old items
+/// don't fit into the region.
+fn splice_items(
+    source: &str,
+    old: &[Item],
+    new: &[Item],
+    region: Range<usize>,
+    depth: usize,
+    style: Style,
+) -> anyhow::Result<Option<String>> {
+    let Some(ranges) = item_ranges(source, old, &region) else {
+        return Ok(None);
+    };
+    // The whitespace and comments in front of an item belong to it.
+    let gap = |i: usize| {
+        let start = if i == 0 {
+            region.start
+        } else {
+            ranges[i - 1].end
+        };
+        &source[start..ranges[i].start]
+    };
+
+    let old_keys = old.iter().map(key).collect::<Vec<_>>();
+    let new_keys = new.iter().map(key).collect::<Vec<_>>();
+
+    let mut out = String::new();
+    for op in similar::capture_diff_slices(Algorithm::Myers, &old_keys, &new_keys) {
+        match op {
+            DiffOp::Equal { old_index, len, .. } => {
+                for i in old_index..old_index + len {
+                    out.push_str(gap(i));
+                    out.push_str(&source[ranges[i].clone()]);
+                }
+            }
+            DiffOp::Delete { .. } => {}
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => {
+                out.push('\n');
+                out.push_str(&INDENT.repeat(depth));
+                out.push_str(&render(&new[new_index..new_index + new_len], depth, style)?);
+            }
+            DiffOp::Replace {
+                old_index,
+                old_len,
+                new_index,
+                new_len,
+            } if old_len == new_len => {
+                for (old_index, new_index) in (old_index..old_index + old_len).zip(new_index..) {
+                    out.push_str(gap(old_index));
+                    match splice_module(
+                        source,
+                        &old[old_index],
+                        &new[new_index],
+                        ranges[old_index].clone(),
+                        depth,
+                        style,
+                    )? {
+                        Some(module) => out.push_str(&module),
+                        None => out.push_str(&render(&new[new_index..=new_index], depth, style)?),
+                    }
+                }
+            }
+            DiffOp::Replace {
+                old_index,
+                new_index,
+                new_len,
+                ..
+            } => {
+                out.push_str(gap(old_index));
+                out.push_str(&render(&new[new_index..new_index + new_len], depth, style)?);
+            }
+        }
+    }
+
+    let end = ranges.last().map_or(region.start, |range| range.end);
+    out.push_str(&source[end..region.end]);
+
+    // The whitespace at the start of the region stays, even if the first item was deleted.
+    let text = &source[region];
+    let leading = &text[..text.len() - text.trim_start().len()];
+    Ok(Some(format!("{leading}{}", out.trim_start())))
+}
+
+/// Splices an inline module that has only changed inside, so that the rest of it is kept.
+fn splice_module(
+    source: &str,
+    old: &Item,
+    new: &Item,
+    range: Range<usize>,
+    depth: usize,
+    style: Style,
+) -> anyhow::Result<Option<String>> {
+    let (Item::Mod(old), Item::Mod(new)) = (old, new) else {
+        return Ok(None);
+    };
+    let (Some((brace, old_items)), Some((_, new_items))) = (&old.content, &new.content) else {
+        return Ok(None);
+    };
+    let without_items = |module: &syn::ItemMod| {
+        let mut module = module.clone();
+        if let Some((_, items)) = &mut module.content {
+            items.clear();
+        }
+        key(&module)
+    };
+    if without_items(old) != without_items(new) {
+        return Ok(None);
+    }
+
+    let open = brace.span.open().byte_range().end;
+    let close = brace.span.close().byte_range().start;
+    let inner_attrs = old
+        .attrs
+        .iter()
+        .filter(|attr| matches!(attr.style, AttrStyle::Inner(_)))
+        .cloned()
+        .collect::<Vec<_>>();
+    let start = end_of_attrs(&inner_attrs, open);
+    let fits = range.start <= open
+        && start <= close
+        && close <= range.end
+        && source.is_char_boundary(start)
+        && source.is_char_boundary(close);
+    if !fits {
+        return Ok(None);
+    }
+
+    let Some(items) = splice_items(source, old_items, new_items, start..close, depth + 1, style)?
+    else {
+        return Ok(None);
+    };
+    Ok(Some(format!(
+        "{}{items}{}",
+        &source[range.start..start],
+        &source[close..range.end]
+    )))
+}
+
+const INDENT: &str = "    ";
+
+/// Renders items as if they were nested in `depth` modules. The first line is not indented,
+/// since the whitespace in front of it comes from the source.
+fn render(items: &[Item], depth: usize, style: Style) -> anyhow::Result<String> {
+    let mut items = items.to_vec();
+    for _ in 0..depth {
+        items = vec![syn::parse_quote!(mod m { #(#items)* })];
+    }
+    let rendered = format(
+        syn::File {
+            shebang: None,
+            attrs: Vec::new(),
+            items,
+        },
+        style,
+    )?;
+    let lines = rendered.trim_end().lines().collect::<Vec<_>>();
+    let Some(inner) = lines.get(depth..lines.len().saturating_sub(depth)) else {
+        anyhow::bail!("rendering nested items: unexpected output {rendered:?}");
+    };
+    Ok(inner.join("\n").trim().to_owned())
+}
+
+/// The byte ranges of the items in the source, including their attributes.
+fn item_ranges(source: &str, items: &[Item], region: &Range<usize>) -> Option<Vec<Range<usize>>> {
+    let mut last = region.start;
+    items
+        .iter()
+        .map(|item| {
+            let range = item.span().byte_range();
+            let fits = last <= range.start
+                && range.start < range.end
+                && range.end <= region.end
+                && source.is_char_boundary(range.start)
+                && source.is_char_boundary(range.end);
+            fits.then(|| {
+                last = range.end;
+                range
+            })
+        })
+        .collect()
+}
+
+fn end_of_attrs(attrs: &[syn::Attribute], start: usize) -> usize {
+    attrs
+        .iter()
+        .map(|attr| attr.span().byte_range().end)
+        .fold(start, usize::max)
+}
+
+fn key(tokens: &impl ToTokens) -> String {
+    tokens.to_token_stream().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Formatter, Style, edition, format, splice};
+
+    fn splice_with(source: &str, change: impl FnOnce(&mut syn::File)) -> String {
+        let old = syn::parse_file(source).unwrap();
+        let mut new = old.clone();
+        change(&mut new);
+        splice(source, &old, &new, Style::default()).unwrap()
+    }
+
+    #[test]
+    fn keeps_unchanged_items() {
+        let source = "#![allow(unused)]\n\n// a header\nfn a() {}\n\n// ~MINIMIZE-ROOT b\nfn   b() { /* keep */ }\n\n// c\nfn c() {}\n";
+        assert_eq!(
+            splice_with(source, |file| {
+                file.items.remove(2);
+            }),
+            "#![allow(unused)]\n\n// a header\nfn a() {}\n\n// ~MINIMIZE-ROOT b\nfn   b() { /* keep */ }\n"
+        );
+        assert_eq!(
+            splice_with(source, |file| {
+                file.items.remove(0);
+            }),
+            "#![allow(unused)]\n\n// ~MINIMIZE-ROOT b\nfn   b() { /* keep */ }\n\n// c\nfn c() {}\n"
+        );
+    }
+
+    #[test]
+    fn renders_changed_items() {
+        let source = "fn a() {\n    1 + 2 // sum\n}\n\nmod m {\n    // inner\n    fn   b() {}\n    fn c() { loop {} }\n}\n";
+        assert_eq!(
+            splice_with(source, |file| {
+                file.items[0] = syn::parse_quote!(
+                    fn a() {
+                        loop {}
+                    }
+                );
+                let syn::Item::Mod(module) = &mut file.items[1] else {
+                    unreachable!()
+                };
+                module.content.as_mut().unwrap().1[1] = syn::parse_quote!(
+                    fn c() {
+                        let x = 1;
+                    }
+                );
+            }),
+            "fn a() {\n    loop { }\n}\n\nmod m {\n    // inner\n    fn   b() {}\n    fn c() {\n        let x = 1;\n    }\n}\n"
+        );
+    }
+
+    #[test]
+    fn formats_with_prettyplease() {
+        let file = syn::parse_quote! {
+            /// Docs.
+            fn main() { let x = 1; }
+        };
+        assert_eq!(
+            format(
+                file,
+                Style {
+                    formatter: Formatter::Prettyplease,
+                    edition: None
+                }
+            )
+            .unwrap(),
+            "/// Docs.\nfn main() {\n    let x = 1;\n}\n"
+        );
+    }
+
+    #[test]
+    fn edition_from_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let write = |path: &str, content: &str| {
+            let path = dir.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        };
+        write(
+            "Cargo.toml",
+            "[workspace]\nmembers = [\"a\", \"b\"]\n\n[workspace.package]\nedition = \"2021\"\n",
+        );
+        write("a/Cargo.toml", "[package]\nname = \"a\"\n");
+        write("a/src/main.rs", "");
+        write(
+            "b/Cargo.toml",
+            "[package]\nname = \"b\"\nedition.workspace = true\n",
+        );
+        write("b/src/main.rs", "");
+
+        assert_eq!(edition(&dir.path().join("a/src/main.rs")), Some(2015));
+        assert_eq!(edition(&dir.path().join("b/src/main.rs")), Some(2021));
+    }
 }
@@ -2,18 +2,24 @@
 extern crate tracing;
 
 use std::{
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
     sync::{Arc, atomic::AtomicBool},
 };
 
 mod build;
+mod config;
 mod dylib_flag;
+mod events;
 mod formatting;
 mod passes;
 mod processor;
+mod scratch;
 
-pub use build::rustup_which;
+pub use build::{Accept, rustup_which};
+pub use events::MessageFormat;
+pub use formatting::Formatter;
+pub use processor::{Bisection, Cancelled, PassSummary, Size, SizeMetric, Summary};
 
 // this experimental and doesnt really work
 #[cfg(any())]
@@ -25,7 +31,7 @@ use processor::{Minimizer, PassSelection};
 use tracing::Level;
 use tracing_subscriber::{EnvFilter, Registry, layer::SubscriberExt, util::SubscriberInitExt};
 
-use crate::processor::Pass;
+use crate::config::Step;
 
 // Export so that the user doesn't have to add clap themselves.
 pub use clap::Parser;
@@ -111,6 +117,164 @@ pub struct Options {
     /// Remove individual use statements manually, instead of relying on rustc lints output
     #[arg(long)]
     pub bisect_delete_imports: bool,
+
+    /// The number of builds to run in parallel. With more than one job, every job gets its own copy of the
+    /// project directory (without the `target` directory) and its own target directory.
+    /// The project directory has to contain everything that is needed for the build.
+    #[arg(long, short, default_value = "1")]
+    pub jobs: usize,
+
+    /// A file to save the results of builds in. Later runs on the same project with the same options skip
+    /// the builds for code that has already been checked. Changing the toolchain, a manifest, the lockfile
+    /// or other sources of the project starts over. Results are always cached during a single run.
+    #[arg(long)]
+    pub cache_file: Option<PathBuf>,
+
+    /// A file to save the progress of the minimization in, so that an interrupted run can be continued
+    /// with `--resume`. The file is deleted once the minimization has finished.
+    #[arg(long)]
+    pub state_file: Option<PathBuf>,
+
+    /// Continue the minimization from the progress saved in the `--state-file`.
+    #[arg(long, requires = "state_file")]
+    pub resume: bool,
+
+    /// Run all passes again and again until a whole round of them could not make any more changes.
+    /// Later passes often allow earlier passes to make progress again.
+    #[arg(long)]
+    pub fixpoint: bool,
+
+    /// The maximum number of rounds for `--fixpoint`.
+    #[arg(long, requires = "fixpoint")]
+    pub max_rounds: Option<usize>,
+
+    /// Kill builds that take longer than this many seconds, together with all processes they started.
+    /// These builds run in their own process group, so a Ctrl-C doesn't reach them. The run stops
+    /// once the current build has finished or timed out.
+    #[arg(long)]
+    pub timeout: Option<u64>,
+
+    /// Count builds that time out as reproducing the issue, which allows minimizing hangs of the compiler.
+    /// By default, they count as not reproducing.
+    #[arg(long, requires = "timeout")]
+    pub timeout_reproduces: bool,
+
+    /// Run every build this many times, for issues that don't reproduce reliably.
+    #[arg(long, default_value = "1")]
+    pub runs: usize,
+
+    /// How many of the `--runs` of a build must reproduce the issue for the build to count as reproducing.
+    #[arg(long, value_enum, default_value_t)]
+    pub accept: Accept,
+
+    /// Check that the final result still reproduces with this many runs, using the `--accept` rule.
+    /// The run fails if it doesn't. 0 skips the check.
+    #[arg(long, default_value = "1")]
+    pub final_runs: usize,
+
+    /// How a set of changes that does not reproduce is split up further. `ddmin` also tries all changes but one
+    /// part of the set, which works better when the changes depend on each other. Can be set for every pass
+    /// in the config file.
+    #[arg(long, value_enum, default_value_t)]
+    pub bisection: Bisection,
+
+    /// A config file that sets options and the pipeline of passes. By default, `minimize.toml` in the
+    /// project directory is used if it exists. Options on the command line take precedence.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+
+    /// Minimize a copy of the project (or only of the path, if it is outside of the project directory) and
+    /// write the result to this directory, which must be empty or not exist yet. The original code is not changed.
+    /// Can't be combined with `--state-file`, since the copy is deleted when the run ends.
+    /// If the run fails, what has been minimized so far is still written.
+    #[arg(long, conflicts_with = "state_file")]
+    pub output: Option<PathBuf>,
+
+    /// Commit every change to this git branch, with the pass and the changed items in the commit message.
+    /// The branch starts at `HEAD` and can be bisected to find the step where the reproduction went wrong.
+    /// The index and `HEAD` of the repository are not changed. With `--resume`, the existing branch is continued.
+    /// Can't be combined with `--output`, since the copy is not in the repository.
+    #[arg(long, conflicts_with = "output")]
+    pub history_branch: Option<String>,
+
+    /// Allow `--history-branch` to start on a working tree with uncommitted changes.
+    /// This is not checked with `--resume`.
+    #[arg(long, requires = "history_branch")]
+    pub allow_dirty: bool,
+
+    /// With `json`, every pass start and finish, tried set of changes, build result, commit and rollback and
+    /// the final summary are written to stdout as one JSON object per line. The log on stderr stays the same.
+    #[arg(long, value_enum, default_value_t)]
+    pub message_format: MessageFormat,
+
+    /// Only list the minimization sites that every pass would try for every file, without building or
+    /// changing anything. Passes that need a build to find their candidates are skipped.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Show the diff of every change that reproduces the issue and ask whether to keep it. Rejected changes
+    /// are treated like changes that don't reproduce.
+    #[arg(long)]
+    pub interactive: bool,
+
+    /// Write every change to this directory as a numbered patch, with the pass in the file name. The patches
+    /// can be applied in order with `git apply` in the project directory. With `--resume`, the series is continued.
+    #[arg(long)]
+    pub emit_patches: Option<PathBuf>,
+
+    /// Reject changes that don't make the file smaller, measured with `--size-metric`, without building them.
+    /// Passes that make the code bigger on purpose, `split-use`, `privatize` and `closure-simplifier`, are exempt.
+    #[arg(long)]
+    pub require_shrink: bool,
+
+    /// How the size is measured for `--require-shrink`.
+    #[arg(long, value_enum, default_value_t)]
+    pub size_metric: SizeMetric,
+
+    /// Only render the items that a pass changed and keep the rest of every file as it was, including comments.
+    /// Without this, every changed file is formatted completely, which drops all comments that aren't doc comments.
+    #[arg(long)]
+    pub preserve_formatting: bool,
+
+    /// How changed code is formatted. If the formatter fails, the others are tried instead.
+    #[arg(long, value_enum, default_value_t)]
+    pub formatter: Formatter,
+
+    /// The ids of the options that were given on the command line, see `Cargo::parse_args`. They win over
+    /// the config file even when they are set to their default value.
+    #[arg(skip)]
+    pub explicit_options: Vec<String>,
+}
+
+impl Cargo {
+    /// Parses the command line like `Parser::parse`, but also remembers which options were given on it.
+    pub fn parse_args() -> Self {
+        let mut command = <Self as clap::CommandFactory>::command();
+        let matches = command.get_matches_mut();
+        Self::from_matches(&matches).unwrap_or_else(|err| err.format(&mut command).exit())
+    }
+
+    fn from_matches(matches: &clap::ArgMatches) -> Result<Self, clap::Error> {
+        let mut cargo = <Self as clap::FromArgMatches>::from_arg_matches(matches)?;
+        let Cargo::Minimize(options) = &mut cargo;
+        if let Some((_, matches)) = matches.subcommand() {
+            options.explicit_options = matches
+                .ids()
+                .filter(|id| {
+                    matches.value_source(id.as_str())
+                        == Some(clap::parser::ValueSource::CommandLine)
+                })
+                .map(ToString::to_string)
+                .collect();
+        }
+        Ok(cargo)
+    }
+}
+
+impl Options {
+    pub(crate) fn is_explicit(&self, id: &str) -> bool {
+        self.explicit_options.iter().any(|explicit| explicit == id)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -135,31 +299,200 @@ impl FromStr for EnvVar {
     }
 }
 
-pub fn minimize(options: Options, stop: Arc<AtomicBool>) -> Result<()> {
+/// How a minimization that didn't fail ended, with the statistics of the run.
+#[derive(Debug)]
+pub enum Outcome {
+    Finished(Summary),
+    /// The `stop` flag was set. The change that was being tried has been rolled back.
+    Cancelled(Cancelled, Summary),
+    /// Only the candidates were listed because of `--dry-run`.
+    DryRun,
+}
+
+pub fn minimize(mut options: Options, stop: Arc<AtomicBool>) -> Result<Outcome> {
+    let config = config::Config::find(&options)?;
+    if let Some((path, config)) = &config {
+        info!("Using config file {}", path.display());
+        let dir = path.parent().unwrap_or(Path::new("."));
+        config.apply(&mut options, dir)?;
+    }
+    let pipeline = config
+        .as_ref()
+        .and_then(|(_, config)| config.pipeline())
+        .map_or_else(config::default_pipeline, ToOwned::to_owned);
+
     for ignore_file in &options.ignore_file {
         if !ignore_file.try_exists()? {
             warn!("Ignored path {} does not exist", ignore_file.display());
         }
     }
 
-    let build = build::Build::new(&options)?;
+    if options.dry_run {
+        let files = processor::collect_files(&options)?;
+        let events = events::Events::new(options.message_format);
+        let mut seen = Vec::new();
+        dry_run_steps(&mut options, &files, &pipeline, events, &mut seen);
+        return Ok(Outcome::DryRun);
+    }
+
+    let scratch = match options.output.clone() {
+        Some(output) => Some(scratch::Scratch::new(&mut options, output)?),
+        None => None,
+    };
+
+    let mut build = build::Build::new(&options)?;
+    if let Some(scratch) = &scratch {
+        build.share_cache_with(scratch.original());
+    }
+    let state_file = options.state_file.clone();
+    let (fixpoint, max_rounds) = (options.fixpoint, options.max_rounds);
+    let final_runs = options.final_runs;
+    let events = events::Events::new(options.message_format);
 
     let mut minimizer = Minimizer::new_glob_dir(options, build, stop)?;
 
-    minimizer.run_passes([
-        passes::EverybodyLoops::default().boxed(),
-        passes::SplitUse::default().boxed(),
-        passes::FieldDeleter::default().boxed(),
-        passes::Privatize::default().boxed(),
-    ])?;
+    let result = match fixpoint {
+        true => run_rounds(&mut minimizer, "all passes", max_rounds, |minimizer| {
+            run_steps(minimizer, &pipeline)
+        }),
+        false => run_steps(&mut minimizer, &pipeline),
+    };
+    // A Ctrl-C during the final check cancels the run like one during the passes.
+    let result = result.and_then(|()| match final_runs {
+        0 => Ok(()),
+        _ => minimizer.check_final(final_runs),
+    });
+    if let Err(err) = result {
+        let cancelled = match err.downcast::<Cancelled>() {
+            Ok(cancelled) => cancelled,
+            Err(err) => {
+                // The copy is deleted, so write out what has been minimized so far.
+                if let Some(scratch) = &scratch
+                    && let Err(write_err) = scratch.write_output()
+                {
+                    error!("Failed to write the output: {write_err:?}");
+                }
+                return Err(err);
+            }
+        };
+        if let Some(scratch) = &scratch {
+            scratch.write_output()?;
+        }
+        let summary = minimizer.summary();
+        events.emit(events::Event::Summary {
+            cancelled: true,
+            summary: &summary,
+        });
+        return Ok(Outcome::Cancelled(cancelled, summary));
+    }
+
+    if let Some(scratch) = &scratch {
+        scratch.write_output()?;
+    }
 
-    minimizer.delete_dead_code().context("deleting dead code")?;
+    if let Some(state_file) = state_file
+        && state_file.try_exists()?
+    {
+        std::fs::remove_file(&state_file)
+            .with_context(|| format!("removing state file {}", state_file.display()))?;
+    }
 
-    minimizer.run_passes([passes::ItemDeleter::default().boxed()])?;
+    let summary = minimizer.summary();
+    events.emit(events::Event::Summary {
+        cancelled: false,
+        summary: &summary,
+    });
+    Ok(Outcome::Finished(summary))
+}
 
+fn run_steps(minimizer: &mut Minimizer, steps: &[Step]) -> Result<()> {
+    for step in steps {
+        match step {
+            Step::Name(name) => run_pass(minimizer, name)?,
+            Step::Pass(step) => {
+                let previous = step.options().apply(minimizer.options_mut());
+                let result = run_pass(minimizer, &step.pass);
+                previous.apply(minimizer.options_mut());
+                result?;
+            }
+            Step::Group(group) => {
+                run_rounds(minimizer, "group", group.max_rounds, |minimizer| {
+                    run_steps(minimizer, &group.group)
+                })?;
+            }
+        }
+    }
     Ok(())
 }
 
+/// Lists the candidates of every pass in the pipeline once. Passes in groups are only listed the first time.
+fn dry_run_steps(
+    options: &mut Options,
+    files: &[processor::SourceFile],
+    steps: &[Step],
+    events: events::Events,
+    seen: &mut Vec<String>,
+) {
+    for step in steps {
+        match step {
+            Step::Name(name) | Step::Pass(config::PassStep { pass: name, .. }) => {
+                if seen.contains(name) {
+                    continue;
+                }
+                seen.push(name.clone());
+
+                let previous = match step {
+                    Step::Pass(step) => Some(step.options().apply(options)),
+                    _ => None,
+                };
+                processor::dry_run_pass(files, options, name, events);
+                if let Some(previous) = previous {
+                    previous.apply(options);
+                }
+            }
+            Step::Group(group) => dry_run_steps(options, files, &group.group, events, seen),
+        }
+    }
+}
+
+fn run_pass(minimizer: &mut Minimizer, name: &str) -> Result<()> {
+    match passes::by_name(name) {
+        Some(pass) => minimizer.run_passes([pass]),
+        None => {
+            assert_eq!(name, processor::DEAD_CODE_PASS_NAME, "unknown pass {name}");
+            minimizer.delete_dead_code().context("deleting dead code")
+        }
+    }
+}
+
+/// Runs the steps again until a round doesn't make any changes or the maximum number of rounds is reached.
+fn run_rounds(
+    minimizer: &mut Minimizer,
+    what: &str,
+    max_rounds: Option<usize>,
+    mut run: impl FnMut(&mut Minimizer) -> Result<()>,
+) -> Result<()> {
+    let mut round = 1;
+    loop {
+        let committed_before = minimizer.committed_changes();
+        // A round that is resumed from a checkpoint may have made changes before the run was interrupted.
+        let resuming = minimizer.is_resuming();
+
+        run(minimizer)?;
+
+        if !resuming && minimizer.committed_changes() == committed_before {
+            info!("Round {round} of {what} did not make any changes");
+            return Ok(());
+        }
+        if max_rounds.is_some_and(|max_rounds| round >= max_rounds) {
+            info!("Stopping {what} after {round} rounds");
+            return Ok(());
+        }
+        round += 1;
+        info!("Starting round {round} of {what}");
+    }
+}
+
 pub fn init_recommended_tracing_subscriber(default_level: Level) {
     let registry = Registry::default().with(
         EnvFilter::builder()
@@ -194,6 +527,31 @@ impl Default for Options {
             ignore_file: Vec::new(),
             no_delete_functions: false,
             bisect_delete_imports: false,
+            jobs: 1,
+            cache_file: None,
+            state_file: None,
+            resume: false,
+            fixpoint: false,
+            max_rounds: None,
+            timeout: None,
+            timeout_reproduces: false,
+            runs: 1,
+            accept: Accept::Any,
+            final_runs: 1,
+            bisection: Bisection::Halving,
+            config: None,
+            output: None,
+            history_branch: None,
+            allow_dirty: false,
+            message_format: MessageFormat::Human,
+            dry_run: false,
+            interactive: false,
+            emit_patches: None,
+            require_shrink: false,
+            size_metric: SizeMetric::Tokens,
+            preserve_formatting: false,
+            formatter: Formatter::Genemichaels,
+            explicit_options: Vec::new(),
         }
     }
 }
@@ -7,11 +7,11 @@ use std::sync::{
 };
 
 use anyhow::Result;
-use cargo_minimize::{Cargo, Parser};
+use cargo_minimize::{Cargo, Outcome, Summary};
 use tracing::{Level, error};
 
 fn main() -> Result<()> {
-    let Cargo::Minimize(options) = Cargo::parse();
+    let Cargo::Minimize(options) = Cargo::parse_args();
 
     cargo_minimize::init_recommended_tracing_subscriber(Level::INFO);
 
@@ -35,5 +35,24 @@ fn main() -> Result<()> {
         error!("Failed to install CTRL-C handler: {err}");
     }
 
-    cargo_minimize::minimize(options, cancel2)
+    let resume_hint = options.state_file.is_some();
+    match cargo_minimize::minimize(options, cancel2)? {
+        Outcome::Finished(summary) => print_summary(&summary),
+        Outcome::DryRun => {}
+        Outcome::Cancelled(cancelled, summary) => {
+            print_summary(&summary);
+            info!("Exiting early, {cancelled}");
+            if resume_hint {
+                info!("Pass --resume to continue");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn print_summary(summary: &Summary) {
+    for line in summary.to_string().lines() {
+        info!("{line}");
+    }
 }
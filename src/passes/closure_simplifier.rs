@@ -0,0 +1,273 @@
+use std::collections::HashSet;
+
+use proc_macro2::{TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::{
+    Expr, ExprClosure, FnArg, Item, Pat, ReturnType, Stmt, parse_quote,
+    punctuated::Punctuated,
+    visit::{self, Visit},
+    visit_mut::VisitMut,
+};
+
+use super::is_loop;
+use crate::processor::{Pass, PassController, ProcessState, SourceFile, tracking};
+
+/// The name of the function that closures are turned into.
+const CLOSURE_FN: &str = "closure";
+
+struct Visitor<'a> {
+    current_path: Vec<String>,
+    checker: &'a mut PassController,
+    process_state: ProcessState,
+    /// The number of the next closure in each enclosing closure (or the file), see `AstPath`.
+    closure_indices: Vec<usize>,
+
+    loop_expr: Expr,
+}
+
+impl<'a> Visitor<'a> {
+    fn new(checker: &'a mut PassController) -> Self {
+        Self {
+            current_path: Vec::new(),
+            checker,
+            process_state: ProcessState::NoChange,
+            closure_indices: vec![0],
+            loop_expr: parse_quote! { { loop {} } },
+        }
+    }
+
+    fn can_process(&mut self, path: &[&str]) -> bool {
+        let len = self.current_path.len();
+        self.current_path
+            .extend(path.iter().map(ToString::to_string));
+        let can_process = self.checker.can_process(&self.current_path);
+        self.current_path.truncate(len);
+
+        if can_process {
+            self.process_state = ProcessState::Changed;
+        }
+        can_process
+    }
+
+    fn next_closure_index(&mut self) -> usize {
+        let index = self.closure_indices.last_mut().unwrap();
+        *index += 1;
+        *index - 1
+    }
+
+    fn with_body_scope(&mut self, f: impl FnOnce(&mut Self)) {
+        self.current_path.push("{{body}}".to_string());
+        self.closure_indices.push(0);
+        f(self);
+        self.closure_indices.pop();
+        self.current_path.pop();
+    }
+
+    fn simplify_closure(&mut self, expr: &mut Expr) {
+        let Expr::Closure(closure) = expr else {
+            unreachable!("simplify_closure called on non-closure expression");
+        };
+
+        // Turning it into a function is the biggest simplification, so everything else about
+        // this closure is nested below it.
+        if can_be_fn(closure) && self.can_process(&[]) {
+            *expr = closure_to_fn(closure);
+            return;
+        }
+
+        for (i, input) in closure.inputs.iter_mut().enumerate() {
+            if let Pat::Type(pat_type) = input
+                && self.can_process(&["{{param}}", &i.to_string()])
+            {
+                *input = (*pat_type.pat).clone();
+            }
+        }
+
+        if let ReturnType::Type(..) = closure.output
+            && self.can_process(&["{{ret}}"])
+        {
+            closure.output = ReturnType::Default;
+        }
+
+        if !is_loop(&closure.body) && self.can_process(&["{{body}}"]) {
+            *closure.body = self.loop_expr.clone();
+        } else {
+            self.with_body_scope(|this| this.visit_expr_mut(&mut closure.body));
+        }
+    }
+}
+
+impl VisitMut for Visitor<'_> {
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        if !matches!(expr, Expr::Closure(_)) && converted_fn(expr).is_none() {
+            syn::visit_mut::visit_expr_mut(self, expr);
+            return;
+        }
+
+        let index = self.next_closure_index();
+        self.current_path.push("{{closure}}".to_string());
+        self.current_path.push(index.to_string());
+
+        match converted_fn(expr) {
+            // Closures that have been turned into functions still take up their index, so
+            // that the closures after them keep their paths.
+            Some(block) => self.with_body_scope(|this| this.visit_block_mut(block)),
+            None => self.simplify_closure(expr),
+        }
+
+        self.current_path.pop();
+        self.current_path.pop();
+    }
+
+    tracking!();
+}
+
+/// Whether the closure can be turned into a function item. This requires all parameter types to be
+/// known and the closure to not capture anything. Since we don't do name resolution, the latter is
+/// approximated by looking for local variables that are used but never bound inside of the closure.
+fn can_be_fn(closure: &ExprClosure) -> bool {
+    if closure.lifetimes.is_some() || closure.constness.is_some() || closure.asyncness.is_some() {
+        return false;
+    }
+    if !closure
+        .inputs
+        .iter()
+        .all(|input| matches!(input, Pat::Type(_)))
+    {
+        return false;
+    }
+
+    let mut finder = CaptureFinder::default();
+    finder.visit_expr_closure(closure);
+
+    finder.used.iter().all(|ident| {
+        finder.bound.contains(ident) || ident.starts_with(|c: char| c.is_ascii_uppercase())
+    })
+}
+
+fn closure_to_fn(closure: &ExprClosure) -> Expr {
+    let ident = syn::Ident::new(CLOSURE_FN, proc_macro2::Span::call_site());
+    let inputs = closure
+        .inputs
+        .iter()
+        .map(|input| match input {
+            Pat::Type(pat_type) => FnArg::Typed(pat_type.clone()),
+            _ => unreachable!("checked in can_be_fn"),
+        })
+        .collect::<Punctuated<FnArg, syn::Token![,]>>();
+    let output = &closure.output;
+    let block: syn::Block = match &*closure.body {
+        Expr::Block(block) if block.label.is_none() && block.attrs.is_empty() => {
+            block.block.clone()
+        }
+        body => parse_quote! { { #body } },
+    };
+
+    parse_quote! {
+        {
+            fn #ident(#inputs) #output #block
+            #ident
+        }
+    }
+}
+
+/// Recognizes a closure that was turned into a function by `closure_to_fn` and returns the body.
+fn converted_fn(expr: &mut Expr) -> Option<&mut syn::Block> {
+    let Expr::Block(block) = expr else {
+        return None;
+    };
+    match block.block.stmts.as_mut_slice() {
+        [
+            Stmt::Item(Item::Fn(func)),
+            Stmt::Expr(Expr::Path(path), None),
+        ] if func.sig.ident == CLOSURE_FN && path.path.is_ident(CLOSURE_FN) => {
+            Some(&mut func.block)
+        }
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct CaptureFinder {
+    used: HashSet<String>,
+    bound: HashSet<String>,
+}
+
+impl CaptureFinder {
+    fn visit_tokens(&mut self, tokens: TokenStream) {
+        for token in tokens {
+            match token {
+                // Keywords like `if` are identifiers in token streams as well, but don't capture anything.
+                TokenTree::Ident(ident)
+                    if ident == "self"
+                        || syn::parse2::<syn::Ident>(ident.to_token_stream()).is_ok() =>
+                {
+                    self.used.insert(ident.to_string());
+                }
+                TokenTree::Group(group) => self.visit_tokens(group.stream()),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for CaptureFinder {
+    fn visit_expr_path(&mut self, path: &'ast syn::ExprPath) {
+        if path.qself.is_none()
+            && let Some(ident) = path.path.get_ident()
+        {
+            self.used.insert(ident.to_string());
+        }
+        visit::visit_expr_path(self, path);
+    }
+
+    fn visit_expr_call(&mut self, call: &'ast syn::ExprCall) {
+        // Calling a function by name is far more common than calling a captured closure.
+        match &*call.func {
+            Expr::Path(_) => {
+                for arg in &call.args {
+                    self.visit_expr(arg);
+                }
+            }
+            _ => visit::visit_expr_call(self, call),
+        }
+    }
+
+    fn visit_pat_ident(&mut self, pat: &'ast syn::PatIdent) {
+        self.bound.insert(pat.ident.to_string());
+        visit::visit_pat_ident(self, pat);
+    }
+
+    fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+        // We can't know what the macro does with the tokens, so assume that all identifiers are used.
+        self.visit_tokens(mac.tokens.clone());
+    }
+
+    // Nested items can't capture anything.
+    fn visit_item(&mut self, _: &'ast Item) {}
+}
+
+#[derive(Default)]
+pub struct ClosureSimplifier {}
+
+impl Pass for ClosureSimplifier {
+    fn process_file(
+        &mut self,
+        krate: &mut syn::File,
+        _: &SourceFile,
+        checker: &mut PassController,
+    ) -> ProcessState {
+        let mut visitor = Visitor::new(checker);
+        visitor.visit_file_mut(krate);
+        visitor.process_state
+    }
+
+    fn name(&self) -> &'static str {
+        "closure-simplifier"
+    }
+
+    // Turning a closure into a function adds tokens, even though it removes the captures.
+    fn may_grow(&self) -> bool {
+        true
+    }
+}
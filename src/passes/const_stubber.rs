@@ -0,0 +1,171 @@
+use quote::ToTokens;
+use syn::{Expr, Lit, Type, parse_quote, visit_mut::VisitMut};
+
+use super::is_loop;
+use crate::processor::{Pass, PassController, ProcessState, SourceFile, tracking};
+
+struct Visitor<'a> {
+    current_path: Vec<String>,
+    checker: &'a mut PassController,
+    process_state: ProcessState,
+    // Array lengths are identified by their position in the enclosing initializer (or the file), since
+    // they don't have a name.
+    len_indices: Vec<usize>,
+}
+
+impl<'a> Visitor<'a> {
+    fn new(checker: &'a mut PassController) -> Self {
+        Self {
+            current_path: Vec::new(),
+            checker,
+            process_state: ProcessState::NoChange,
+            len_indices: vec![0],
+        }
+    }
+
+    /// Tries the stubs for an initializer one after another. Every stub is nested below the previous one,
+    /// so that the later ones are only tried once the earlier ones failed and are dropped once one of them
+    /// was committed.
+    fn stub_initializer(&mut self, name: String, expr: &mut Expr, ty: Option<&Type>) {
+        if is_stub(expr) {
+            return;
+        }
+
+        let len = self.current_path.len();
+        self.current_path.push(name);
+
+        let stubs = ty
+            .and_then(zero_like)
+            .map(|zero| ("{{zero}}", zero))
+            .into_iter()
+            .chain([
+                ("{{todo}}", parse_quote! { todo!() }),
+                ("{{loop}}", parse_quote! { loop {} }),
+            ]);
+
+        for (label, stub) in stubs {
+            self.current_path.push(label.to_string());
+            if self.checker.can_process(&self.current_path) {
+                self.process_state = ProcessState::Changed;
+                *expr = stub;
+                self.current_path.truncate(len);
+                return;
+            }
+        }
+
+        // None of the stubs were applied, so look for array lengths inside of the initializer.
+        self.len_indices.push(0);
+        self.visit_expr_mut(expr);
+        self.len_indices.pop();
+
+        self.current_path.truncate(len);
+    }
+
+    fn stub_len(&mut self, len: &mut Expr) {
+        let index = self.len_indices.last_mut().unwrap();
+        *index += 1;
+        let name = format!("{{{{len}}}} {}", *index - 1);
+
+        self.stub_initializer(name, len, Some(&parse_quote! { usize }));
+    }
+}
+
+impl VisitMut for Visitor<'_> {
+    fn visit_item_const_mut(&mut self, const_: &mut syn::ItemConst) {
+        self.visit_type_mut(&mut const_.ty);
+        self.stub_initializer(const_.ident.to_string(), &mut const_.expr, Some(&const_.ty));
+    }
+
+    fn visit_item_static_mut(&mut self, static_: &mut syn::ItemStatic) {
+        self.visit_type_mut(&mut static_.ty);
+        self.stub_initializer(
+            static_.ident.to_string(),
+            &mut static_.expr,
+            Some(&static_.ty),
+        );
+    }
+
+    fn visit_impl_item_const_mut(&mut self, const_: &mut syn::ImplItemConst) {
+        self.visit_type_mut(&mut const_.ty);
+        self.stub_initializer(const_.ident.to_string(), &mut const_.expr, Some(&const_.ty));
+    }
+
+    fn visit_trait_item_const_mut(&mut self, const_: &mut syn::TraitItemConst) {
+        self.visit_type_mut(&mut const_.ty);
+        if let Some((_, expr)) = &mut const_.default {
+            self.stub_initializer(const_.ident.to_string(), expr, Some(&const_.ty));
+        }
+    }
+
+    fn visit_type_array_mut(&mut self, array: &mut syn::TypeArray) {
+        self.visit_type_mut(&mut array.elem);
+        self.stub_len(&mut array.len);
+    }
+
+    fn visit_expr_repeat_mut(&mut self, repeat: &mut syn::ExprRepeat) {
+        self.visit_expr_mut(&mut repeat.expr);
+        self.stub_len(&mut repeat.len);
+    }
+
+    tracking!();
+}
+
+/// A literal of the type that's as boring as possible, if we know one.
+fn zero_like(ty: &Type) -> Option<Expr> {
+    match ty {
+        Type::Path(path) if path.qself.is_none() => {
+            let ident = path.path.get_ident()?.to_string();
+            match ident.as_str() {
+                "u8" | "u16" | "u32" | "u64" | "u128" | "usize" | "i8" | "i16" | "i32" | "i64"
+                | "i128" | "isize" => Some(parse_quote! { 0 }),
+                "f32" | "f64" => Some(parse_quote! { 0.0 }),
+                "bool" => Some(parse_quote! { false }),
+                "char" => Some(parse_quote! { '\0' }),
+                _ => None,
+            }
+        }
+        Type::Reference(reference) => match &*reference.elem {
+            Type::Path(path) if path.path.is_ident("str") => Some(parse_quote! { "" }),
+            _ => None,
+        },
+        Type::Tuple(tuple) if tuple.elems.is_empty() => Some(parse_quote! { () }),
+        _ => None,
+    }
+}
+
+/// Whether the expression is one of our stubs already. There is no point in replacing these again.
+fn is_stub(expr: &Expr) -> bool {
+    match expr {
+        Expr::Lit(lit) => match &lit.lit {
+            Lit::Int(int) => int.base10_digits() == "0",
+            Lit::Float(float) => float.base10_parse::<f64>().is_ok_and(|f| f == 0.0),
+            Lit::Bool(bool) => !bool.value,
+            Lit::Char(char) => char.value() == '\0',
+            Lit::Str(str) => str.value().is_empty(),
+            _ => false,
+        },
+        Expr::Tuple(tuple) => tuple.elems.is_empty(),
+        Expr::Macro(mac) => mac.mac.path.is_ident("todo") && mac.mac.tokens.is_empty(),
+        expr => is_loop(expr),
+    }
+}
+
+#[derive(Default)]
+pub struct ConstStubber {}
+
+impl Pass for ConstStubber {
+    fn process_file(
+        &mut self,
+        krate: &mut syn::File,
+        _: &SourceFile,
+        checker: &mut PassController,
+    ) -> ProcessState {
+        let mut visitor = Visitor::new(checker);
+        visitor.visit_file_mut(krate);
+        visitor.process_state
+    }
+
+    fn name(&self) -> &'static str {
+        "const-stubber"
+    }
+}
@@ -1,7 +1,10 @@
 use quote::ToTokens;
+use std::collections::{HashMap, VecDeque};
 use syn::{
-    Item, ItemConst, ItemEnum, ItemExternCrate, ItemFn, ItemMacro, ItemMod, ItemStatic, ItemStruct,
-    ItemTrait, ItemTraitAlias, ItemType, ItemUnion, ItemUse, Signature, visit_mut::VisitMut,
+    ForeignItem, ForeignItemFn, ForeignItemStatic, ForeignItemType, ImplItem, ImplItemConst,
+    ImplItemFn, ImplItemType, Item, ItemConst, ItemEnum, ItemExternCrate, ItemFn, ItemForeignMod,
+    ItemImpl, ItemMacro, ItemMod, ItemStatic, ItemStruct, ItemTrait, ItemTraitAlias, ItemType,
+    ItemUnion, ItemUse, Signature, Stmt, visit_mut::VisitMut,
 };
 
 use crate::processor::{Pass, PassController, ProcessState, SourceFile, tracking};
@@ -10,6 +13,18 @@ struct Visitor<'a> {
     current_path: Vec<String>,
     checker: &'a mut PassController,
     process_state: ProcessState,
+    /// The files, modules and blocks that are being visited.
+    scopes: Vec<Scope>,
+}
+
+/// The unnamed items of a file, module or block.
+#[derive(Default)]
+struct Scope {
+    /// How many of each empty extern block there are.
+    extern_blocks: HashMap<String, usize>,
+    anon_consts: usize,
+    /// The indices of the anonymous consts that are kept, in order, for visiting the items in them.
+    kept_anon_consts: VecDeque<usize>,
 }
 
 impl<'a> Visitor<'a> {
@@ -18,29 +33,47 @@ impl<'a> Visitor<'a> {
             current_path: Vec::new(),
             checker,
             process_state: ProcessState::NoChange,
+            scopes: Vec::new(),
         }
     }
 
+    fn with_item_scope(&mut self, f: impl FnOnce(&mut Self)) {
+        self.scopes.push(Scope::default());
+        f(self);
+        self.scopes.pop();
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes
+            .last_mut()
+            .expect("items are considered in a scope")
+    }
+
+    /// Empty extern blocks are identified by their tokens. Identical ones are numbered, deleting one of
+    /// them doesn't matter for the others since they are interchangeable.
+    fn extern_block_segment(&mut self, block: &ItemForeignMod) -> String {
+        let tokens = block.to_token_stream().to_string();
+        let count = self
+            .scope()
+            .extern_blocks
+            .entry(tokens.clone())
+            .or_default();
+        *count += 1;
+        format!("{{{{extern}}}} {tokens} {}", *count - 1)
+    }
+
     fn should_retain_item(&mut self) -> bool {
         let can_process = self.checker.can_process(&self.current_path);
-        if can_process {
+        if can_process && self.process_state == ProcessState::NoChange {
             self.process_state = ProcessState::Changed;
         }
         !can_process
     }
 
-    fn consider_deleting_item(&mut self, item: &Item) -> bool {
+    fn consider_deleting_item(&mut self, item: &mut Item) -> bool {
         match item {
             Item::Impl(impl_) => {
-                self.current_path.push(format!(
-                    "({}) for ({})",
-                    impl_
-                        .trait_
-                        .as_ref()
-                        .map(|(_, tr, _)| tr.into_token_stream().to_string())
-                        .unwrap_or_default(),
-                    impl_.self_ty.clone().into_token_stream()
-                ));
+                self.current_path.push(impl_path_segment(impl_));
 
                 let should_retain = self.should_retain_item();
 
@@ -48,6 +81,22 @@ impl<'a> Visitor<'a> {
                 should_retain
             }
             Item::Fn(_) if self.checker.options.no_delete_functions => true,
+            Item::Const(ItemConst { ident, .. }) if ident == "_" => {
+                let index = self.scope().anon_consts;
+                self.scope().anon_consts += 1;
+                self.current_path.push(anon_const_segment(index));
+
+                let should_retain = self.should_retain_item();
+
+                self.current_path.pop();
+                if should_retain {
+                    self.scope().kept_anon_consts.push_back(index);
+                } else {
+                    // This renumbers the anonymous consts after it.
+                    self.process_state = ProcessState::FileInvalidated;
+                }
+                should_retain
+            }
             Item::Fn(ItemFn {
                 sig: Signature { ident, .. },
                 ..
@@ -82,37 +131,169 @@ impl<'a> Visitor<'a> {
                 self.current_path.pop();
                 should_retain
             }
-            Item::ForeignMod(_) => true,
+            Item::ForeignMod(block) => {
+                block
+                    .items
+                    .retain(|item| self.consider_deleting_foreign_item(item));
+
+                // Only delete the block itself once everything in it is gone, as it may still
+                // carry attributes like `#[link]`.
+                if block.items.is_empty() {
+                    let segment = self.extern_block_segment(block);
+                    self.current_path.push(segment);
+
+                    let should_retain = self.should_retain_item();
+
+                    self.current_path.pop();
+                    should_retain
+                } else {
+                    true
+                }
+            }
             Item::Verbatim(_) => true,
             _ => true,
         }
     }
+
+    fn consider_deleting_foreign_item(&mut self, item: &ForeignItem) -> bool {
+        match item {
+            ForeignItem::Fn(_) if self.checker.options.no_delete_functions => true,
+            ForeignItem::Fn(ForeignItemFn {
+                sig: Signature { ident, .. },
+                ..
+            })
+            | ForeignItem::Static(ForeignItemStatic { ident, .. })
+            | ForeignItem::Type(ForeignItemType { ident, .. }) => {
+                self.current_path.push(ident.to_string());
+
+                let should_retain = self.should_retain_item();
+
+                self.current_path.pop();
+                should_retain
+            }
+            _ => true,
+        }
+    }
+
+    fn consider_deleting_impl_item(&mut self, item: &ImplItem) -> bool {
+        match item {
+            ImplItem::Fn(_) if self.checker.options.no_delete_functions => true,
+            ImplItem::Fn(ImplItemFn {
+                sig: Signature { ident, .. },
+                ..
+            })
+            | ImplItem::Const(ImplItemConst { ident, .. })
+            | ImplItem::Type(ImplItemType { ident, .. }) => {
+                self.current_path.push(ident.to_string());
+
+                let should_retain = self.should_retain_item();
+
+                self.current_path.pop();
+                should_retain
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Anonymous consts are all named `_`, so they are numbered within their scope.
+fn anon_const_segment(index: usize) -> String {
+    format!("{{{{const}}}} {index}")
+}
+
+/// The path segment of an impl block. This has to be the same when deleting the impl and its items,
+/// so that the items are not considered anymore once the impl is gone.
+fn impl_path_segment(impl_: &ItemImpl) -> String {
+    format!(
+        "({}) for ({})",
+        impl_
+            .trait_
+            .as_ref()
+            .map(|(_, tr, _)| tr.into_token_stream().to_string())
+            .unwrap_or_default(),
+        impl_.self_ty.clone().into_token_stream()
+    )
 }
 
 impl VisitMut for Visitor<'_> {
     fn visit_file_mut(&mut self, file: &mut syn::File) {
-        file.items
-            .retain_mut(|item| self.consider_deleting_item(item));
+        self.with_item_scope(|this| {
+            file.items
+                .retain_mut(|item| this.consider_deleting_item(item));
 
-        syn::visit_mut::visit_file_mut(self, file);
+            syn::visit_mut::visit_file_mut(this, file);
+        });
     }
 
     fn visit_item_mod_mut(&mut self, module: &mut syn::ItemMod) {
         self.current_path.push(module.ident.to_string());
 
-        if let Some((_, items)) = &mut module.content {
-            items.retain(|item| self.consider_deleting_item(item));
-        }
+        self.with_item_scope(|this| {
+            if let Some((_, items)) = &mut module.content {
+                items.retain_mut(|item| this.consider_deleting_item(item));
+            }
+
+            syn::visit_mut::visit_item_mod_mut(this, module);
+        });
+        self.current_path.pop();
+    }
+
+    // Items can also be declared inside of function bodies and const blocks.
+    fn visit_block_mut(&mut self, block: &mut syn::Block) {
+        self.with_item_scope(|this| {
+            block.stmts.retain_mut(|stmt| match stmt {
+                Stmt::Item(item) => this.consider_deleting_item(item),
+                _ => true,
+            });
+
+            syn::visit_mut::visit_block_mut(this, block);
+        });
+    }
+
+    fn visit_item_impl_mut(&mut self, impl_: &mut ItemImpl) {
+        self.current_path.push(impl_path_segment(impl_));
+
+        impl_
+            .items
+            .retain(|item| self.consider_deleting_impl_item(item));
+
+        syn::visit_mut::visit_item_impl_mut(self, impl_);
+        self.current_path.pop();
+    }
+
+    fn visit_item_const_mut(&mut self, const_: &mut ItemConst) {
+        let segment = if const_.ident == "_" {
+            let index = self
+                .scope()
+                .kept_anon_consts
+                .pop_front()
+                .expect("anonymous consts are counted before the items in them are visited");
+            anon_const_segment(index)
+        } else {
+            const_.ident.to_string()
+        };
+        self.current_path.push(segment);
+        syn::visit_mut::visit_item_const_mut(self, const_);
+        self.current_path.pop();
+    }
+
+    fn visit_item_static_mut(&mut self, static_: &mut ItemStatic) {
+        self.current_path.push(static_.ident.to_string());
+        syn::visit_mut::visit_item_static_mut(self, static_);
+        self.current_path.pop();
+    }
 
-        syn::visit_mut::visit_item_mod_mut(self, module);
+    fn visit_trait_item_fn_mut(&mut self, method: &mut syn::TraitItemFn) {
+        self.current_path.push(method.sig.ident.to_string());
+        syn::visit_mut::visit_trait_item_fn_mut(self, method);
         self.current_path.pop();
     }
 
     tracking!(visit_item_fn_mut);
     tracking!(visit_impl_item_fn_mut);
-    tracking!(visit_item_impl_mut);
     tracking!(visit_field_mut);
     tracking!(visit_item_struct_mut);
+    tracking!(visit_item_trait_mut);
 }
 
 #[derive(Default)]
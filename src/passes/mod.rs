@@ -1,10 +1,44 @@
+mod closure_simplifier;
+mod const_stubber;
 mod everybody_loops;
 mod field_deleter;
 mod item_deleter;
+mod pattern_simplifier;
 mod privatize;
 mod split_use;
 
+use crate::processor::Pass;
+
 pub use self::{
+    closure_simplifier::ClosureSimplifier, const_stubber::ConstStubber,
     everybody_loops::EverybodyLoops, field_deleter::FieldDeleter, item_deleter::ItemDeleter,
-    privatize::Privatize, split_use::SplitUse,
+    pattern_simplifier::PatternSimplifier, privatize::Privatize, split_use::SplitUse,
 };
+
+/// Creates the pass with this name. The dead code reaper isn't a normal pass and is not included.
+pub(crate) fn by_name(name: &str) -> Option<Box<dyn Pass>> {
+    let pass = match name {
+        "everybody-loops" => EverybodyLoops::default().boxed(),
+        "split-use" => SplitUse::default().boxed(),
+        "field-deleter" => FieldDeleter::default().boxed(),
+        "pattern-simplifier" => PatternSimplifier::default().boxed(),
+        "closure-simplifier" => ClosureSimplifier::default().boxed(),
+        "const-stubber" => ConstStubber::default().boxed(),
+        "privatize" => Privatize::default().boxed(),
+        "item-deleter" => ItemDeleter::default().boxed(),
+        _ => return None,
+    };
+    Some(pass)
+}
+
+/// Whether the expression is an empty `loop {}`, optionally wrapped in a block.
+fn is_loop(expr: &syn::Expr) -> bool {
+    match expr {
+        syn::Expr::Loop(loop_) => loop_.body.stmts.is_empty(),
+        syn::Expr::Block(block) => match block.block.stmts.as_slice() {
+            [syn::Stmt::Expr(expr, None)] => is_loop(expr),
+            _ => false,
+        },
+        _ => false,
+    }
+}
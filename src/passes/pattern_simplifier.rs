@@ -0,0 +1,155 @@
+use quote::ToTokens;
+use std::collections::HashMap;
+use syn::{FnArg, Pat, PatWild, visit_mut::VisitMut};
+
+use crate::processor::{Pass, PassController, ProcessState, SourceFile, tracking};
+
+struct Visitor<'a> {
+    current_path: Vec<String>,
+    checker: &'a mut PassController,
+    process_state: ProcessState,
+    /// The number of the next binding site in each item, keyed by the path of the item. See `AstPath`.
+    site_indices: HashMap<Vec<String>, usize>,
+}
+
+impl<'a> Visitor<'a> {
+    fn new(checker: &'a mut PassController) -> Self {
+        Self {
+            current_path: Vec::new(),
+            checker,
+            process_state: ProcessState::NoChange,
+            site_indices: HashMap::new(),
+        }
+    }
+
+    fn visit_pattern_site(&mut self, pat: &mut Pat) {
+        let index = self
+            .site_indices
+            .entry(self.current_path.clone())
+            .or_default();
+        *index += 1;
+        let index = *index - 1;
+
+        self.current_path.push("{{pat}}".to_string());
+        self.current_path.push(index.to_string());
+
+        self.simplify_pat(pat);
+
+        self.current_path.pop();
+        self.current_path.pop();
+    }
+
+    fn can_process(&mut self) -> bool {
+        let can_process = self.checker.can_process(&self.current_path);
+        if can_process {
+            self.process_state = ProcessState::Changed;
+        }
+        can_process
+    }
+
+    fn simplify_pat(&mut self, pat: &mut Pat) {
+        match pat {
+            Pat::Wild(_) | Pat::Rest(_) | Pat::Verbatim(_) => {}
+            Pat::Ident(ident)
+                if ident.by_ref.is_none()
+                    && ident.mutability.is_none()
+                    && ident.subpat.is_none() => {}
+            // The type is not part of the pattern, `x: T` only has `x` to simplify.
+            Pat::Type(pat_type) => self.simplify_pat(&mut pat_type.pat),
+            Pat::Ident(ident) => {
+                // `ref mut x @ Some(_)` becomes a plain `x`.
+                if self.can_process() {
+                    ident.by_ref = None;
+                    ident.mutability = None;
+                    ident.subpat = None;
+                } else if let Some((_, subpat)) = &mut ident.subpat {
+                    self.simplify_sub_pats([&mut **subpat]);
+                }
+            }
+            _ => {
+                if self.can_process() {
+                    *pat = Pat::Wild(PatWild {
+                        attrs: Vec::new(),
+                        underscore_token: Default::default(),
+                    });
+                } else {
+                    self.simplify_children(pat);
+                }
+            }
+        }
+    }
+
+    fn simplify_children(&mut self, pat: &mut Pat) {
+        match pat {
+            Pat::Or(or) => self.simplify_sub_pats(&mut or.cases),
+            Pat::Paren(paren) => self.simplify_sub_pats([&mut *paren.pat]),
+            Pat::Reference(reference) => self.simplify_sub_pats([&mut *reference.pat]),
+            Pat::Slice(slice) => self.simplify_sub_pats(&mut slice.elems),
+            Pat::Struct(struct_) => {
+                self.simplify_sub_pats(struct_.fields.iter_mut().map(|field| &mut *field.pat))
+            }
+            Pat::Tuple(tuple) => self.simplify_sub_pats(&mut tuple.elems),
+            Pat::TupleStruct(tuple_struct) => self.simplify_sub_pats(&mut tuple_struct.elems),
+            _ => {}
+        }
+    }
+
+    fn simplify_sub_pats<'p>(&mut self, pats: impl IntoIterator<Item = &'p mut Pat>) {
+        for (i, pat) in pats.into_iter().enumerate() {
+            self.current_path.push(i.to_string());
+            self.simplify_pat(pat);
+            self.current_path.pop();
+        }
+    }
+}
+
+impl VisitMut for Visitor<'_> {
+    fn visit_local_mut(&mut self, local: &mut syn::Local) {
+        self.visit_pattern_site(&mut local.pat);
+        syn::visit_mut::visit_local_mut(self, local);
+    }
+
+    fn visit_arm_mut(&mut self, arm: &mut syn::Arm) {
+        self.visit_pattern_site(&mut arm.pat);
+        syn::visit_mut::visit_arm_mut(self, arm);
+    }
+
+    fn visit_expr_let_mut(&mut self, expr_let: &mut syn::ExprLet) {
+        self.visit_pattern_site(&mut expr_let.pat);
+        syn::visit_mut::visit_expr_let_mut(self, expr_let);
+    }
+
+    fn visit_fn_arg_mut(&mut self, arg: &mut FnArg) {
+        if let FnArg::Typed(pat_type) = arg {
+            self.visit_pattern_site(&mut pat_type.pat);
+        }
+        syn::visit_mut::visit_fn_arg_mut(self, arg);
+    }
+
+    // Patterns are only simplified through the sites above, everything else (like closure parameters)
+    // is left alone. This also keeps us from walking into expressions nested in patterns, which could
+    // contain sites of their own and mess up the site numbering once the pattern is gone.
+    fn visit_pat_mut(&mut self, _: &mut Pat) {}
+
+    tracking!();
+}
+
+#[derive(Default)]
+pub struct PatternSimplifier {}
+
+impl Pass for PatternSimplifier {
+    fn process_file(
+        &mut self,
+        krate: &mut syn::File,
+        _: &SourceFile,
+        checker: &mut PassController,
+    ) -> ProcessState {
+        let mut visitor = Visitor::new(checker);
+        visitor.visit_file_mut(krate);
+        visitor.process_state
+    }
+
+    fn name(&self) -> &'static str {
+        "pattern-simplifier"
+    }
+}
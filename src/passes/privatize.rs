@@ -72,4 +72,8 @@ impl Pass for Privatize {
     fn name(&self) -> &'static str {
         "privatize"
     }
+
+    fn may_grow(&self) -> bool {
+        true
+    }
 }
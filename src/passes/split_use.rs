@@ -178,4 +178,8 @@ impl Pass for SplitUse {
     fn name(&self) -> &'static str {
         "split-use"
     }
+
+    fn may_grow(&self) -> bool {
+        true
+    }
 }
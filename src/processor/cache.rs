@@ -0,0 +1,156 @@
+//! Remembers whether a state of the source files reproduces the issue.
+
+use anyhow::{Context, Result};
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    fs::{self, File},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// The hash of the build configuration and the contents of all files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) struct CacheKey(u128);
+
+impl CacheKey {
+    /// Hashes the build fingerprint and all files. This uses FNV-1a instead of the std hasher,
+    /// since the hash must be stable across runs (and compiler versions) for the cache file.
+    pub(crate) fn new<'a, P: AsRef<Path>>(
+        fingerprint: &str,
+        files: impl IntoIterator<Item = (P, &'a str)>,
+    ) -> Self {
+        const OFFSET: u128 = 0x6c62272e07bb014262b821756295c58d;
+        const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+        let mut hash = OFFSET;
+        let mut write = |bytes: &[u8]| {
+            // Prefix everything with its length so that moving bytes between parts changes the hash.
+            for byte in bytes.len().to_le_bytes().iter().chain(bytes) {
+                hash ^= u128::from(*byte);
+                hash = hash.wrapping_mul(PRIME);
+            }
+        };
+
+        write(fingerprint.as_bytes());
+        for (path, content) in files {
+            write(path.as_ref().as_os_str().as_encoded_bytes());
+            write(content.as_bytes());
+        }
+
+        Self(hash)
+    }
+}
+
+impl Display for CacheKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:032x}", self.0)
+    }
+}
+
+/// The results of all builds so far, optionally backed by a file that results are appended to.
+#[derive(Debug)]
+pub(crate) struct BuildCache {
+    results: HashMap<CacheKey, bool>,
+    file: Option<(PathBuf, File)>,
+}
+
+impl BuildCache {
+    pub(crate) fn new(path: Option<&Path>) -> Result<Self> {
+        let mut results = HashMap::new();
+
+        let Some(path) = path else {
+            return Ok(Self {
+                results,
+                file: None,
+            });
+        };
+
+        if path.try_exists()? {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("reading cache file {}", path.display()))?;
+            // Every line is `<hash> <0 or 1>`. Lines that don't parse are ignored, they may be
+            // left over from a run that was killed while writing.
+            for line in content.lines() {
+                let Some((key, reproduces)) = line.split_once(' ') else {
+                    continue;
+                };
+                let (Ok(key), Ok(reproduces)) =
+                    (u128::from_str_radix(key, 16), reproduces.parse::<u8>())
+                else {
+                    continue;
+                };
+                results.insert(CacheKey(key), reproduces == 1);
+            }
+            info!(
+                "Loaded {} build results from {}",
+                results.len(),
+                path.display()
+            );
+        }
+
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening cache file {}", path.display()))?;
+
+        Ok(Self {
+            results,
+            file: Some((path.to_owned(), file)),
+        })
+    }
+
+    pub(crate) fn get(&self, key: CacheKey) -> Option<bool> {
+        self.results.get(&key).copied()
+    }
+
+    pub(crate) fn insert(&mut self, key: CacheKey, reproduces: bool) -> Result<()> {
+        if self.results.insert(key, reproduces).is_some() {
+            return Ok(());
+        }
+
+        if let Some((path, file)) = &mut self.file {
+            writeln!(file, "{key} {}", u8::from(reproduces))
+                .with_context(|| format!("writing cache file {}", path.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{BuildCache, CacheKey};
+
+    #[test]
+    fn key_depends_on_everything() {
+        let key = |fingerprint, content| CacheKey::new(fingerprint, [(Path::new("a.rs"), content)]);
+
+        assert_eq!(key("build", "fn main() {}"), key("build", "fn main() {}"));
+        assert_ne!(key("build", "fn main() {}"), key("check", "fn main() {}"));
+        assert_ne!(key("build", "fn main() {}"), key("build", "fn main() { }"));
+        assert_ne!(
+            CacheKey::new("", [(Path::new("a.rs"), "b.rs")]),
+            CacheKey::new("", [(Path::new("a.rsb"), ".rs")])
+        );
+    }
+
+    #[test]
+    fn cache_file_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("cache");
+        let yes = CacheKey::new("", [(Path::new("a.rs"), "yes")]);
+        let no = CacheKey::new("", [(Path::new("a.rs"), "no")]);
+
+        let mut cache = BuildCache::new(Some(&path)).unwrap();
+        cache.insert(yes, true).unwrap();
+        cache.insert(no, false).unwrap();
+        drop(cache);
+
+        let cache = BuildCache::new(Some(&path)).unwrap();
+        assert_eq!(cache.get(yes), Some(true));
+        assert_eq!(cache.get(no), Some(false));
+    }
+}
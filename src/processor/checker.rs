@@ -1,10 +1,40 @@
-use std::{borrow::Borrow, collections::BTreeSet, fmt::Debug, mem};
+use std::{
+    borrow::Borrow,
+    collections::BTreeSet,
+    fmt::{Debug, Display},
+    mem,
+};
+
+use serde::{Deserialize, Serialize};
 
 use crate::Options;
 
-use self::worklist::Worklist;
+use self::worklist::{WorkItem, Worklist};
+
+/// How a set of candidates that does not reproduce is split up further.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Bisection {
+    /// Split the set in half and try both halves.
+    #[default]
+    Halving,
+    /// Split the set into four chunks like Zeller's ddmin and first try all candidates except one chunk,
+    /// then the chunks on their own. This keeps candidates that depend on each other together for longer.
+    Ddmin,
+}
+
+/// The number of chunks that `Bisection::Ddmin` splits a set into.
+const DDMIN_CHUNKS: usize = 4;
 
-#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+/// Identifies a minimization site, usually by the names of the items it is in and its own name.
+///
+/// The paths are collected once and then used for the whole bisection, so a path has to keep pointing
+/// to the same site while the sites around it are changed. Sites without a name, like patterns or closures,
+/// are numbered by their position instead of being identified by their contents, since the contents change
+/// as soon as something inside of them is simplified. The numbering is local to the item or closure that
+/// the site is in, so that a change only renumbers the sites next to it. A pass whose changes do shift the
+/// numbers of other sites has to invalidate the file, so that the paths are collected again.
+#[derive(Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 struct AstPath(Vec<String>);
 impl AstPath {
     fn has_prefix(&self, other: &AstPath) -> bool {
@@ -27,6 +57,12 @@ impl Debug for AstPath {
     }
 }
 
+impl Display for AstPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0.join("::"))
+    }
+}
+
 /// `PassController` is the interface between the passes and the core logic.
 /// Its job is to bisect down the minimization sites so that all the ones that can be applied
 /// are applied while trying to apply as many as possible in batches.
@@ -34,10 +70,19 @@ impl Debug for AstPath {
 pub(crate) struct PassController {
     state: PassControllerState,
     pub(crate) options: Options,
+    sites: SiteCounts,
+}
+
+/// How the minimization sites of a file have ended up, see [`PassController::sites`].
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SiteCounts {
+    pub(crate) candidates: usize,
+    pub(crate) committed: usize,
+    pub(crate) failed: usize,
 }
 
 /// The current state of the bisection.
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 enum PassControllerState {
     /// Initially, we have a bunch of candidates (minimization sites) that could be applied.
     /// We collect them in the initial application of the pass where we try to apply all candiates.
@@ -55,6 +100,9 @@ enum PassControllerState {
         failed: BTreeSet<AstPath>,
         /// The set of candidates that we want to apply in this iteration.
         current: BTreeSet<AstPath>,
+        /// Whether `current` is a complement, see [`WorkItem::complement`].
+        #[serde(default)]
+        complement: bool,
         /// The list of `current`s that we want to try in the future.
         worklist: Worklist,
     },
@@ -62,12 +110,26 @@ enum PassControllerState {
     Success,
 }
 
+/// The state of a [`PassController`] as it is saved in a checkpoint, see [`PassController::checkpoint`].
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct PassControllerCheckpoint(PassControllerState);
+
 mod worklist {
+    use serde::{Deserialize, Serialize};
+
     use super::AstPath;
 
     /// A worklist that ensures that the inner list is never empty.
-    #[derive(Debug)]
-    pub(super) struct Worklist(Vec<Vec<AstPath>>);
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub(super) struct Worklist(Vec<WorkItem>);
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    pub(super) struct WorkItem {
+        pub(super) paths: Vec<AstPath>,
+        /// The set is all candidates of a failed set except for one of its chunks (see `Bisection::Ddmin`).
+        /// It is not split up further when it fails, since the chunks are in the worklist as well.
+        pub(super) complement: bool,
+    }
 
     impl Worklist {
         pub(super) fn new() -> Self {
@@ -75,12 +137,30 @@ mod worklist {
         }
 
         pub(super) fn push(&mut self, next: Vec<AstPath>) {
-            if !next.is_empty() {
+            self.push_item(WorkItem {
+                paths: next,
+                complement: false,
+            });
+        }
+
+        pub(super) fn push_complement(&mut self, next: Vec<AstPath>) {
+            self.push_item(WorkItem {
+                paths: next,
+                complement: true,
+            });
+        }
+
+        pub(super) fn push_item(&mut self, next: WorkItem) {
+            if !next.paths.is_empty() {
                 self.0.push(next);
             }
         }
 
-        pub(super) fn pop(&mut self) -> Option<Vec<AstPath>> {
+        pub(super) fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        pub(super) fn pop(&mut self) -> Option<WorkItem> {
             self.0.pop()
         }
 
@@ -90,12 +170,23 @@ mod worklist {
         // trying to delete that module's individual items anymore
         pub(super) fn prune(&mut self, things: &std::collections::BTreeSet<AstPath>) {
             for wl in &mut self.0 {
-                wl.retain(|path| {
+                wl.paths.retain(|path| {
                     // retain only if none of the things are a prefix of this path
                     things.iter().all(|thing| !path.has_prefix(thing))
                 })
             }
-            self.0.retain(|wl| !wl.is_empty());
+            self.0.retain(|wl| !wl.paths.is_empty());
+
+            // Once a complement has been committed, the other complements of the same set shrink down
+            // to the remaining chunk, which is in the worklist already.
+            let chunks = self
+                .0
+                .iter()
+                .filter(|wl| !wl.complement)
+                .map(|wl| wl.paths.clone())
+                .collect::<Vec<_>>();
+            self.0
+                .retain(|wl| !wl.complement || !chunks.contains(&wl.paths));
         }
     }
 }
@@ -107,19 +198,36 @@ impl PassController {
                 candidates: Vec::new(),
             },
             options,
+            sites: SiteCounts::default(),
         }
     }
 
+    /// Continues the bisection from a state that was saved with [`PassController::checkpoint`].
+    pub fn resume(options: Options, checkpoint: PassControllerCheckpoint) -> Self {
+        Self {
+            state: checkpoint.0,
+            options,
+            sites: SiteCounts::default(),
+        }
+    }
+
+    pub fn checkpoint(&self) -> PassControllerCheckpoint {
+        PassControllerCheckpoint(self.state.clone())
+    }
+
     pub fn reproduces(&mut self) {
         match &mut self.state {
-            PassControllerState::InitialCollection { .. } => {
+            PassControllerState::InitialCollection { candidates } => {
+                // Everything has been applied at once.
+                self.sites.candidates = candidates.len();
+                self.sites.committed = candidates.len();
                 self.state = PassControllerState::Success;
             }
             PassControllerState::Bisecting {
                 committed,
-                failed: _,
                 current,
                 worklist,
+                ..
             } => {
                 worklist.prune(current);
                 committed.extend(mem::take(current));
@@ -142,6 +250,7 @@ impl PassController {
                 committed,
                 failed,
                 current,
+                complement,
                 worklist,
             } => {
                 debug!(
@@ -152,16 +261,11 @@ impl PassController {
                     "Does not reproduce"
                 );
 
-                if current.len() == 1 {
-                    // We are at a leaf. This is a failure.
-                    failed.extend(mem::take(current));
-                } else {
-                    // Split it further and add it to the worklist.
-                    let (first_half, second_half) = split_owned(mem::take(current));
-
-                    worklist.push(first_half);
-                    worklist.push(second_half);
-                }
+                let set = WorkItem {
+                    paths: mem::take(current).into_iter().collect(),
+                    complement: *complement,
+                };
+                bisect_failed(set, self.options.bisection, failed, worklist);
 
                 self.next_in_worklist()
             }
@@ -169,10 +273,117 @@ impl PassController {
         }
     }
 
+    /// Whether the controller is bisecting and can therefore hand out several candidate sets at once
+    /// with [`PassController::speculate`].
+    pub fn is_bisecting(&self) -> bool {
+        matches!(self.state, PassControllerState::Bisecting { .. })
+    }
+
+    /// Takes up to `max` candidate sets from the worklist so that they can be tried at the same time.
+    /// Every returned controller only allows its own set to be applied.
+    /// The results must be reported back with [`PassController::speculation_results`].
+    pub fn speculate(&mut self, max: usize) -> Vec<PassController> {
+        let PassControllerState::Bisecting {
+            current,
+            complement,
+            worklist,
+            ..
+        } = &mut self.state
+        else {
+            unreachable!("speculate called on non-bisecting state");
+        };
+
+        let mut sets = vec![WorkItem {
+            paths: mem::take(current).into_iter().collect(),
+            complement: *complement,
+        }];
+        while sets.len() < max {
+            match worklist.pop() {
+                Some(next) => sets.push(next),
+                None => break,
+            }
+        }
+
+        sets.into_iter()
+            .map(|set| PassController {
+                state: PassControllerState::Bisecting {
+                    committed: BTreeSet::new(),
+                    failed: BTreeSet::new(),
+                    current: set.paths.into_iter().collect(),
+                    complement: set.complement,
+                    worklist: Worklist::new(),
+                },
+                options: self.options.clone(),
+                sites: SiteCounts::default(),
+            })
+            .collect()
+    }
+
+    /// Reports the results of the controllers from [`PassController::speculate`], in the same order.
+    /// The first set that reproduces is the one that has been committed. All sets before it failed and are
+    /// bisected further, all sets after it have to be tried again on top of the committed one.
+    pub fn speculation_results(&mut self, speculations: Vec<PassController>, reproduces: &[bool]) {
+        let PassControllerState::Bisecting {
+            committed,
+            failed,
+            current,
+            worklist,
+            ..
+        } = &mut self.state
+        else {
+            unreachable!("speculation_results called on non-bisecting state");
+        };
+        assert!(current.is_empty(), "speculation results reported twice");
+        assert_eq!(speculations.len(), reproduces.len());
+
+        let mut sets = speculations
+            .into_iter()
+            .map(|speculation| match speculation.state {
+                PassControllerState::Bisecting {
+                    current,
+                    complement,
+                    ..
+                } => WorkItem {
+                    paths: current.into_iter().collect(),
+                    complement,
+                },
+                _ => unreachable!("speculation is always bisecting"),
+            })
+            .collect::<Vec<_>>();
+
+        let first_reproducing = reproduces.iter().position(|&reproduces| reproduces);
+        let committed_set = first_reproducing.map(|i| {
+            let untested = sets.split_off(i + 1);
+            for set in untested.into_iter().rev() {
+                worklist.push_item(set);
+            }
+            sets.pop().unwrap()
+        });
+
+        debug!(
+            ?committed_set,
+            failed_sets = ?sets,
+            "Speculation results"
+        );
+
+        for set in sets.into_iter().rev() {
+            bisect_failed(set, self.options.bisection, failed, worklist);
+        }
+
+        if let Some(set) = committed_set {
+            let set = set.paths.into_iter().collect();
+            worklist.prune(&set);
+            committed.extend(set);
+        }
+
+        self.next_in_worklist();
+    }
+
     /// The pass did not apply any changes. We're either done or just starting
     pub fn no_change(&mut self) {
         match &mut self.state {
             PassControllerState::InitialCollection { candidates } => {
+                self.sites.candidates = candidates.len();
                 if candidates.is_empty() {
                     self.state = PassControllerState::Success;
                 } else {
@@ -185,12 +396,13 @@ impl PassController {
                         // .rev() so that we add shorter paths last, and process them first
                         worklist.push(layer);
                     }
-                    let current = worklist.pop().unwrap().into_iter().collect();
+                    let current = worklist.pop().unwrap().paths.into_iter().collect();
 
                     self.state = PassControllerState::Bisecting {
                         committed: BTreeSet::new(),
                         failed: BTreeSet::new(),
                         current,
+                        complement: false,
                         worklist,
                     };
                 }
@@ -212,6 +424,45 @@ impl PassController {
         }
     }
 
+    /// The number of sites that were found, committed and failed so far. After resuming from a checkpoint,
+    /// the sites from before the checkpoint are not counted.
+    pub fn sites(&self) -> SiteCounts {
+        let mut sites = self.sites;
+        if let PassControllerState::Bisecting {
+            committed, failed, ..
+        } = &self.state
+        {
+            sites.committed += committed.len();
+            sites.failed += failed.len();
+        }
+        sites
+    }
+
+    /// The number of candidate sets that are left to try, including the current one. `None` if the
+    /// controller isn't bisecting.
+    pub fn sets_left(&self) -> Option<usize> {
+        match &self.state {
+            PassControllerState::Bisecting {
+                current, worklist, ..
+            } => Some(worklist.len() + usize::from(!current.is_empty())),
+            _ => None,
+        }
+    }
+
+    /// The minimization sites that the pass may apply right now. During the initial collection,
+    /// these are all candidates that have been collected so far.
+    pub fn current_paths(&self) -> Vec<String> {
+        match &self.state {
+            PassControllerState::InitialCollection { candidates } => {
+                candidates.iter().map(ToString::to_string).collect()
+            }
+            PassControllerState::Bisecting { current, .. } => {
+                current.iter().map(ToString::to_string).collect()
+            }
+            PassControllerState::Success => Vec::new(),
+        }
+    }
+
     /// Checks whether a pass may apply the changes for a minimization site.
     pub fn can_process(&mut self, path: &[String]) -> bool {
         match &mut self.state {
@@ -229,23 +480,84 @@ impl PassController {
 
     fn next_in_worklist(&mut self) {
         let PassControllerState::Bisecting {
-            current, worklist, ..
+            committed,
+            failed,
+            current,
+            complement,
+            worklist,
         } = &mut self.state
         else {
             unreachable!("next_in_worklist called on non-bisecting state");
         };
         match worklist.pop() {
             Some(next) => {
-                *current = next.into_iter().collect();
+                *current = next.paths.into_iter().collect();
+                *complement = next.complement;
                 trace!(?current, "current working set: ");
             }
             None => {
+                self.sites.committed += committed.len();
+                self.sites.failed += failed.len();
                 self.state = PassControllerState::Success;
             }
         }
     }
 }
 
+/// A set of candidates did not reproduce the regression, so bisect it further.
+fn bisect_failed(
+    set: WorkItem,
+    bisection: Bisection,
+    failed: &mut BTreeSet<AstPath>,
+    worklist: &mut Worklist,
+) {
+    if set.complement {
+        // The chunks of the complement are still in the worklist, so there is nothing left to do.
+        return;
+    }
+
+    if set.paths.len() == 1 {
+        // We are at a leaf. This is a failure.
+        failed.extend(set.paths);
+        return;
+    }
+
+    match bisection {
+        Bisection::Halving => {
+            // Split it further and add it to the worklist.
+            let (first_half, second_half) = split_owned(set.paths);
+
+            worklist.push(first_half);
+            worklist.push(second_half);
+        }
+        Bisection::Ddmin => {
+            let chunk_size = div_ceil(set.paths.len(), DDMIN_CHUNKS);
+            let chunks = set
+                .paths
+                .chunks(chunk_size)
+                .map(<[AstPath]>::to_vec)
+                .collect::<Vec<_>>();
+
+            for chunk in &chunks {
+                worklist.push(chunk.clone());
+            }
+            // With two chunks, the complements are just the chunks again.
+            // Otherwise, they are pushed last so that they are tried first.
+            if chunks.len() > 2 {
+                for i in 0..chunks.len() {
+                    let complement = chunks
+                        .iter()
+                        .enumerate()
+                        .filter(|&(j, _)| j != i)
+                        .flat_map(|(_, chunk)| chunk.iter().cloned())
+                        .collect();
+                    worklist.push_complement(complement);
+                }
+            }
+        }
+    }
+}
+
 // copied from `core` because who needs stable features anyways
 // update: still not stabilized because of bikeshedding for div_floor.
 pub const fn div_ceil(lhs: usize, rhs: usize) -> usize {
@@ -298,3 +610,97 @@ fn layer_candidates(mut candidates: Vec<AstPath>) -> Vec<Vec<AstPath>> {
     }
     layers
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Bisection, PassController};
+    use crate::Options;
+
+    fn path(name: &str) -> Vec<String> {
+        vec![name.to_owned()]
+    }
+
+    fn bisecting(candidates: &[&str]) -> PassController {
+        bisecting_with(Options::default(), candidates)
+    }
+
+    fn bisecting_with(options: Options, candidates: &[&str]) -> PassController {
+        let mut checker = PassController::new(options);
+        for candidate in candidates {
+            checker.can_process(&path(candidate));
+        }
+        checker.no_change();
+        assert!(checker.is_bisecting());
+        checker
+    }
+
+    #[test]
+    fn speculation_commits_first_reproducing() {
+        let mut checker = bisecting(&["a", "b", "c", "d"]);
+        // The initial set with everything failed and was split up.
+        checker.does_not_reproduce();
+
+        let mut speculations = checker.speculate(2);
+        assert_eq!(speculations.len(), 2);
+        assert!(speculations[0].can_process(&path("c")));
+        assert!(speculations[1].can_process(&path("a")));
+
+        checker.speculation_results(speculations, &[false, true]);
+
+        // "c" and "d" failed together and are split up, "a" and "b" are done.
+        let remaining = checker.speculate(4);
+        assert_eq!(remaining.len(), 2);
+        checker.speculation_results(remaining, &[true, true]);
+
+        // Only the first reproducing set is committed, the other one has to be tried again.
+        assert!(!checker.is_finished());
+        let remaining = checker.speculate(4);
+        assert_eq!(remaining.len(), 1);
+        checker.speculation_results(remaining, &[true]);
+        assert!(checker.is_finished());
+    }
+
+    #[test]
+    fn speculation_retries_sets_after_committed() {
+        let mut checker = bisecting(&["a", "b", "c", "d"]);
+        checker.does_not_reproduce();
+        checker.does_not_reproduce();
+
+        // The worklist is now ["a", "b"], ["c"] with ["d"] being current.
+        let speculations = checker.speculate(3);
+        assert_eq!(speculations.len(), 3);
+
+        // "d" reproduces, so the results for the sets after it are discarded and they are tried again.
+        checker.speculation_results(speculations, &[true, true, false]);
+        let mut retried = checker.speculate(3);
+        assert_eq!(retried.len(), 2);
+        assert!(retried[0].can_process(&path("c")));
+        assert!(retried[1].can_process(&path("a")));
+    }
+
+    #[test]
+    fn ddmin_tries_complements_first() {
+        let options = Options {
+            bisection: Bisection::Ddmin,
+            ..Options::default()
+        };
+        let mut checker = bisecting_with(options, &["a", "b", "c", "d"]);
+        checker.does_not_reproduce();
+
+        // Everything except the last chunk.
+        assert!(checker.can_process(&path("a")));
+        assert!(checker.can_process(&path("c")));
+        assert!(!checker.can_process(&path("d")));
+        checker.does_not_reproduce();
+
+        // The failed complement is not split up, the next complement is tried instead.
+        assert!(checker.can_process(&path("d")));
+        assert!(!checker.can_process(&path("c")));
+        checker.reproduces();
+
+        // Only "c" is left, all other complements have shrunk down to it and were dropped.
+        assert!(checker.can_process(&path("c")));
+        checker.does_not_reproduce();
+        assert!(checker.is_finished());
+    }
+}
@@ -0,0 +1,43 @@
+//! Saves the progress of a run so that it can be continued after it was interrupted.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::checker::PassControllerCheckpoint;
+
+/// Where we are in the minimization. The files themselves don't need to be saved, they are already
+/// on disk. Only the file that is currently being processed may contain a change that was being tried
+/// when the run was interrupted, so we remember its last committed content.
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+    /// The index of the pass in the whole pipeline, counting disabled passes.
+    pub(crate) pass_index: usize,
+    /// The name of the pass, to detect that the pipeline has changed.
+    pub(crate) pass_name: String,
+    pub(crate) file: PathBuf,
+    pub(crate) file_content: String,
+    pub(crate) controller: PassControllerCheckpoint,
+    pub(crate) invalidated_files: Vec<PathBuf>,
+}
+
+impl Checkpoint {
+    pub(crate) fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("reading state file {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("parsing state file {}", path.display()))
+    }
+
+    /// Saves the checkpoint. The file is replaced at once so that an interruption while saving
+    /// doesn't leave a broken file behind.
+    pub(crate) fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string(self).context("serializing state")?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, content).with_context(|| format!("writing {}", tmp.display()))?;
+        fs::rename(&tmp, path).with_context(|| format!("writing state file {}", path.display()))
+    }
+}
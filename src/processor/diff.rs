@@ -0,0 +1,47 @@
+//! Diffs of the changes to a file.
+
+use owo_colors::OwoColorize;
+use similar::TextDiff;
+
+/// A unified diff with `a/` and `b/` in front of the path like git does it.
+pub(crate) fn unified_diff(path: &str, before: &str, after: &str) -> String {
+    TextDiff::from_lines(before, after)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("a/{path}"), &format!("b/{path}"))
+        .to_string()
+}
+
+/// Colors the added and removed lines of a unified diff.
+pub(crate) fn colored(diff: &str) -> String {
+    diff.lines()
+        .map(|line| {
+            if line.starts_with("+++") || line.starts_with("---") {
+                line.bold().to_string()
+            } else if line.starts_with('+') {
+                line.green().to_string()
+            } else if line.starts_with('-') {
+                line.red().to_string()
+            } else if line.starts_with("@@") {
+                line.cyan().to_string()
+            } else {
+                line.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unified_diff;
+
+    #[test]
+    fn diff_has_git_paths() {
+        let diff = unified_diff("src/main.rs", "fn a() {}\nfn main() {}\n", "fn main() {}\n");
+        assert_eq!(
+            diff,
+            "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,2 +1 @@\n-fn a() {}\n fn main() {}\n"
+        );
+    }
+}
@@ -1,5 +1,10 @@
-use anyhow::Result;
-use std::{fs, path::Path};
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::Options;
 
 pub(crate) use self::file::SourceFile;
 
@@ -11,6 +16,7 @@ mod file {
     };
 
     use super::{Changes, FileChange};
+    use crate::{Formatter, formatting::Style};
 
     /// The representation of a source file, with the cached AST.
     /// IMPORTANT INVARIANT: All file system operations MUST go through this type.
@@ -20,33 +26,91 @@ mod file {
         path: PathBuf,
         content_str: RefCell<String>,
         content: RefCell<syn::File>,
+        style: Style,
+        /// With `--preserve-formatting`, the spans in `content` always point into `content_str`.
+        preserve_formatting: bool,
     }
 
     impl SourceFile {
-        pub(crate) fn open(path: PathBuf) -> Result<Self> {
+        pub(crate) fn open(
+            path: PathBuf,
+            formatter: Formatter,
+            preserve_formatting: bool,
+        ) -> Result<Self> {
             let string = std::fs::read_to_string(&path)
                 .with_context(|| format!("reading file {}", path.display()))?;
             let content = syn::parse_file(&string)
                 .with_context(|| format!("parsing file {}", path.display()))?;
+            let style = Style::for_file(formatter, &path);
             Ok(SourceFile {
                 path,
                 content_str: RefCell::new(string),
                 content: RefCell::new(content),
+                style,
+                preserve_formatting,
             })
         }
 
+        /// The content that `write` would write for the AST.
+        pub(crate) fn render(&self, new: &syn::File) -> Result<String> {
+            Ok(self.render_parsed(new)?.0)
+        }
+
+        /// Renders the AST. With `--preserve-formatting`, this also returns the AST parsed from the output,
+        /// so that the next change can be spliced into the new content.
+        fn render_parsed(&self, new: &syn::File) -> Result<(String, Option<syn::File>)> {
+            if !self.preserve_formatting {
+                return Ok((crate::formatting::format(new.clone(), self.style)?, None));
+            }
+
+            let spliced = crate::formatting::splice(
+                &self.content_str.borrow(),
+                &self.content.borrow(),
+                new,
+                self.style,
+            )?;
+            match syn::parse_file(&spliced) {
+                Ok(parsed) => return Ok((spliced, Some(parsed))),
+                Err(err) => warn!(
+                    "{}: The spliced content doesn't parse ({err}), formatting the whole file instead",
+                    self.path.display()
+                ),
+            }
+
+            let string = crate::formatting::format(new.clone(), self.style)?;
+            let parsed = syn::parse_file(&string).with_context(|| {
+                format!("parsing file {} after formatting", self.path.display())
+            })?;
+            Ok((string, Some(parsed)))
+        }
+
         pub(crate) fn write(&self, new: syn::File) -> Result<()> {
-            let string = crate::formatting::format(new.clone())?;
+            let (string, parsed) = self.render_parsed(&new)?;
+            self.restore(string, parsed.unwrap_or(new))
+        }
+
+        /// Writes content that is known to belong to the AST, like the content from before a change.
+        pub(crate) fn restore(&self, string: String, content: syn::File) -> Result<()> {
             std::fs::write(&self.path, &string)
                 .with_context(|| format!("writing file {}", self.path.display()))?;
             *self.content_str.borrow_mut() = string;
-            *self.content.borrow_mut() = new;
+            *self.content.borrow_mut() = content;
             Ok(())
         }
 
         pub(crate) fn path_no_fs_interact(&self) -> &Path {
             &self.path
         }
+
+        /// A copy of the current AST of the file.
+        pub(crate) fn krate(&self) -> syn::File {
+            self.content.borrow().clone()
+        }
+
+        /// The current content of the file, which is the same as the content on disk.
+        pub(crate) fn content_str(&self) -> String {
+            self.content_str.borrow().clone()
+        }
     }
 
     impl PartialEq for SourceFile {
@@ -107,15 +171,22 @@ impl FileChange<'_, '_> {
     }
 
     pub(crate) fn write(&mut self, new: syn::File) -> Result<()> {
+        // If writing fails, the file may be left with anything in it, so the content from before is put back.
+        if let Err(err) = self.source_file.write(new) {
+            self.has_written_change = false;
+            self.source_file
+                .restore(self.before_content_str.clone(), self.before_content.clone())?;
+            return Err(err);
+        }
         self.has_written_change = true;
-        self.source_file.write(new)?;
         Ok(())
     }
 
     pub(crate) fn rollback(mut self) -> Result<()> {
         assert!(self.has_written_change);
         self.has_written_change = false;
-        self.source_file.write(self.before_content.clone())?;
+        self.source_file
+            .restore(self.before_content_str.clone(), self.before_content.clone())?;
         Ok(())
     }
 
@@ -137,8 +208,40 @@ impl Drop for FileChange<'_, '_> {
     }
 }
 
+/// The directory of the project, which is `--project-dir` or the current directory. The copies for
+/// `--output` and `--jobs` and the paths in patches are relative to it.
+pub(crate) fn project_root(options: &Options) -> Result<PathBuf> {
+    let root = match &options.project_dir {
+        Some(dir) => dir.clone(),
+        None => std::env::current_dir().context("getting current directory")?,
+    };
+    root.canonicalize()
+        .with_context(|| format!("canonicalizing {}", root.display()))
+}
+
+/// The path of a file relative to `root`, which must be canonical. The components are joined with `/`,
+/// as in git and in patches.
+pub(crate) fn relative_path(root: &Path, path: &Path) -> Result<String> {
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("canonicalizing {}", path.display()))?;
+    let relative = canonical
+        .strip_prefix(root)
+        .with_context(|| format!("{} is not inside of {}", path.display(), root.display()))?;
+    Ok(relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/"))
+}
+
 impl Changes {
     pub(crate) fn had_changes(&self) -> bool {
         self.any_change
     }
+
+    /// Records a change that didn't go through a `FileChange`.
+    pub(crate) fn mark_changed(&mut self) {
+        self.any_change = true;
+    }
 }
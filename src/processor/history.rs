@@ -0,0 +1,215 @@
+//! Records every committed change as a commit on a git branch, so that the steps of a run can be bisected.
+
+use anyhow::{Context, Result, bail};
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+use tempfile::TempDir;
+
+use super::{SourceFile, files::relative_path};
+use crate::Options;
+
+/// A branch that gets a commit for every change. The commits are created with a separate index, so
+/// neither the user's index nor `HEAD` are touched.
+#[derive(Debug)]
+pub(crate) struct History {
+    /// The top level directory of the repository.
+    root: PathBuf,
+    reference: String,
+    /// The commit that the branch points to.
+    head: String,
+    index: TempDir,
+}
+
+impl History {
+    /// Creates the branch at `HEAD`. When resuming, the commits are added to the existing branch instead.
+    /// Everything that could make a commit fail later is checked here, before any file is changed.
+    pub(crate) fn new(options: &Options, branch: &str, files: &[SourceFile]) -> Result<Self> {
+        let dir = options.project_dir.as_deref().unwrap_or(Path::new("."));
+        let root = run(
+            Command::new("git")
+                .args(["rev-parse", "--show-toplevel"])
+                .current_dir(dir),
+            None,
+        )
+        .with_context(|| format!("finding the git repository of {}", dir.display()))?;
+        let root = PathBuf::from(root)
+            .canonicalize()
+            .context("canonicalizing repository directory")?;
+
+        // When resuming, the tree contains the changes of the interrupted run.
+        if !options.allow_dirty && !options.resume {
+            // This has to look at the user's index, not the one for the history.
+            let status = run(
+                Command::new("git")
+                    .args(["status", "--porcelain"])
+                    .current_dir(&root),
+                None,
+            )?;
+            if !status.is_empty() {
+                bail!(
+                    "the working tree of {} has uncommitted changes, commit them first or pass --allow-dirty",
+                    root.display()
+                );
+            }
+        }
+
+        for var in ["GIT_AUTHOR_IDENT", "GIT_COMMITTER_IDENT"] {
+            run(
+                Command::new("git").args(["var", var]).current_dir(&root),
+                None,
+            )
+            .context("git needs a name and email to commit with, set user.name and user.email")?;
+        }
+        for file in files {
+            relative_path(&root, file.path_no_fs_interact())
+                .context("all files have to be in the git repository")?;
+        }
+
+        let index = tempfile::Builder::new()
+            .prefix("cargo-minimize-history")
+            .tempdir()
+            .context("creating directory for the git index")?;
+
+        let mut history = Self {
+            root,
+            reference: format!("refs/heads/{branch}"),
+            head: String::new(),
+            index,
+        };
+
+        let existing = run(
+            &mut history.git(["rev-parse", "--verify", "--quiet", &history.reference]),
+            None,
+        )
+        .ok();
+        history.head = match existing {
+            Some(head) if options.resume => head,
+            Some(_) => bail!("the branch {branch} already exists"),
+            None => {
+                let head = run(&mut history.git(["rev-parse", "--verify", "HEAD"]), None)?;
+                run(
+                    &mut history.git(["update-ref", &history.reference, &head, ""]),
+                    None,
+                )
+                .with_context(|| format!("creating branch {branch}"))?;
+                head
+            }
+        };
+        run(&mut history.git(["read-tree", &history.head]), None)
+            .context("reading tree into index")?;
+
+        info!("Recording every change on the branch {branch}");
+
+        Ok(history)
+    }
+
+    /// Commits the current content of the file, which has just been changed by the pass.
+    /// `paths` are the minimization sites that the pass was allowed to apply.
+    pub(crate) fn commit(&mut self, file: &SourceFile, pass: &str, paths: &[String]) -> Result<()> {
+        let relative = relative_path(&self.root, file.path_no_fs_interact())?;
+
+        let blob = run(
+            &mut self.git(["hash-object", "-w", "--stdin"]),
+            Some(&file.content_str()),
+        )?;
+        run(
+            &mut self.git([
+                "update-index",
+                "--add",
+                "--cacheinfo",
+                &format!("100644,{blob},{relative}"),
+            ]),
+            None,
+        )?;
+        let tree = run(&mut self.git(["write-tree"]), None)?;
+        let commit = run(
+            &mut self.git(["commit-tree", &tree, "-p", &self.head]),
+            Some(&message(pass, &relative, paths)),
+        )
+        .context("committing change")?;
+        run(
+            &mut self.git(["update-ref", &self.reference, &commit, &self.head]),
+            None,
+        )?;
+
+        self.head = commit;
+        Ok(())
+    }
+
+    fn git<'a>(&self, args: impl IntoIterator<Item = &'a str>) -> Command {
+        let mut cmd = Command::new("git");
+        cmd.args(args)
+            .current_dir(&self.root)
+            .env("GIT_INDEX_FILE", self.index.path().join("index"));
+        cmd
+    }
+}
+
+/// The message of the commit for a change, also used for the patches.
+pub(super) fn message(pass: &str, file: &str, paths: &[String]) -> String {
+    let mut message = format!("{pass}: {file}\n");
+    if !paths.is_empty() {
+        message.push('\n');
+        for path in paths {
+            message.push_str(&format!("- {path}\n"));
+        }
+    }
+    message
+}
+
+/// Runs git and returns its trimmed stdout.
+fn run(cmd: &mut Command, stdin: Option<&str>) -> Result<String> {
+    cmd.stdin(match stdin {
+        Some(_) => Stdio::piped(),
+        None => Stdio::null(),
+    })
+    .stdout(Stdio::piped())
+    .stderr(Stdio::piped());
+
+    let mut child = cmd.spawn().context("spawning git")?;
+    if let Some(stdin) = stdin {
+        child
+            .stdin
+            .take()
+            .expect("stdin is piped")
+            .write_all(stdin.as_bytes())
+            .context("writing to git")?;
+    }
+    let output = child.wait_with_output().context("waiting for git")?;
+
+    if !output.status.success() {
+        bail!(
+            "{cmd:?} failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)
+        .context("git output is not UTF-8")?
+        .trim()
+        .to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::message;
+
+    #[test]
+    fn message_lists_paths() {
+        assert_eq!(
+            message("item-deleter", "src/main.rs", &[]),
+            "item-deleter: src/main.rs\n"
+        );
+        assert_eq!(
+            message(
+                "everybody-loops",
+                "src/lib.rs",
+                &["a::b".to_owned(), "c".to_owned()]
+            ),
+            "everybody-loops: src/lib.rs\n\n- a::b\n- c\n"
+        );
+    }
+}
@@ -1,16 +1,46 @@
+mod cache;
 mod checker;
+mod checkpoint;
+mod diff;
 mod files;
+mod history;
+mod patches;
+mod progress;
 mod reaper;
+mod review;
+mod summary;
+mod workers;
 
-pub(crate) use self::files::SourceFile;
-use crate::{Options, build::Build, processor::files::Changes};
-use anyhow::{Context, Result, bail};
+pub(crate) use self::cache::CacheKey;
+pub(crate) use self::files::{SourceFile, project_root};
+pub(crate) use self::reaper::PASS_NAME as DEAD_CODE_PASS_NAME;
+use crate::{
+    Options,
+    build::{Build, BuildResult},
+    events::{Event, Events},
+    processor::{
+        cache::BuildCache,
+        checkpoint::Checkpoint,
+        files::{Changes, FileChange},
+        history::History,
+        patches::PatchSeries,
+        progress::Progress,
+        review::Review,
+        summary::Statistics,
+        workers::WorkerPool,
+    },
+};
+use anyhow::{Context, Result, bail, ensure};
 use owo_colors::OwoColorize;
+use std::cell::{Cell, RefCell};
 use std::sync::Arc;
 use std::sync::atomic::Ordering;
+use std::time::Instant;
 use std::{collections::HashSet, ffi::OsStr, fmt::Debug, sync::atomic::AtomicBool};
 
+pub use self::checker::Bisection;
 pub(crate) use self::checker::PassController;
+pub use self::summary::{PassSummary, Size, SizeMetric, Summary};
 
 pub(crate) trait Pass {
     fn refresh_state(&mut self) -> Result<()> {
@@ -29,6 +59,12 @@ pub(crate) trait Pass {
 
     fn name(&self) -> &'static str;
 
+    /// Whether the pass makes the code bigger on purpose, so that later passes can make progress.
+    /// Its changes are kept even if they don't shrink the code with `--require-shrink`.
+    fn may_grow(&self) -> bool {
+        false
+    }
+
     fn boxed(self) -> Box<dyn Pass>
     where
         Self: Sized + 'static,
@@ -77,21 +113,63 @@ impl std::str::FromStr for PassSelection {
     }
 }
 
+/// The run was cancelled before it finished. All files have been left in the last state that reproduced the issue.
+#[derive(Debug)]
+pub struct Cancelled {
+    /// The pass that was running, if any.
+    pub pass: Option<String>,
+    /// The number of passes in the pipeline that were done, counting disabled passes.
+    pub finished_passes: usize,
+    /// The number of changes that were committed over all passes.
+    pub committed_changes: usize,
+}
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.pass {
+            Some(pass) => write!(f, "cancelled during {pass}")?,
+            None => f.write_str("cancelled")?,
+        }
+        write!(
+            f,
+            " after {} finished passes and {} committed changes",
+            self.finished_passes, self.committed_changes
+        )
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
 #[derive(Debug)]
 pub(crate) struct Minimizer {
     files: Vec<SourceFile>,
     build: Build,
+    /// Only present when more than one job was requested.
+    workers: Option<RefCell<WorkerPool>>,
+    cache: RefCell<BuildCache>,
+    /// Only present when every change should be committed to a git branch.
+    history: Option<RefCell<History>>,
+    /// Only present with `--emit-patches`.
+    patches: Option<PatchSeries>,
+    /// The checkpoint to resume from, until the pass that it was saved in is reached.
+    resume: RefCell<Option<Checkpoint>>,
+    /// The index of the next pass in the whole pipeline, counting disabled passes.
+    pass_index: Cell<usize>,
+    /// The pass that is running right now.
+    current_pass: Cell<Option<&'static str>>,
+    committed_changes: Cell<usize>,
+    progress: Progress,
+    statistics: Statistics,
+    events: Events,
+    /// Only present with `--interactive`.
+    review: Option<Review>,
     options: Options,
     cancel: Arc<AtomicBool>,
 }
 
 impl Minimizer {
     fn pass_enabled(&self, name: &str) -> bool {
-        match &self.options.passes {
-            None => true,
-            Some(PassSelection::Enable(v)) => v.iter().any(|allowed| name == allowed),
-            Some(PassSelection::Disable(v)) => v.iter().all(|forbidden| name != forbidden),
-        }
+        pass_enabled(&self.options, name)
     }
 
     pub(crate) fn new_glob_dir(
@@ -99,50 +177,61 @@ impl Minimizer {
         build: Build,
         cancel: Arc<AtomicBool>,
     ) -> Result<Self> {
-        let path = &options.path;
-        let walk = walkdir::WalkDir::new(path);
+        let files = collect_files(&options)?;
+        let statistics = Statistics::new(&files);
 
-        let files = walk
-            .into_iter()
-            .filter_map(|entry| match entry {
-                Ok(entry) => Some(entry),
-                Err(err) => {
-                    warn!("Error during walkdir: {err}");
-                    None
-                }
-            })
-            .filter(|entry| entry.path().extension() == Some(OsStr::new("rs")))
-            .filter(|entry| {
-                if options
-                    .ignore_file
-                    .iter()
-                    .any(|ignored| entry.path().starts_with(ignored))
-                {
-                    info!("Ignoring file: {}", entry.path().display());
-                    false
-                } else {
-                    true
-                }
-            })
-            .map(|entry| SourceFile::open(entry.into_path()))
-            .inspect(|file| {
-                if let Ok(file) = file {
-                    info!("Collecting file: {file:?}");
-                }
-            })
-            .collect::<Result<Vec<_>>>()?;
+        let workers = match options.jobs {
+            0 => bail!("--jobs must be at least 1"),
+            1 => None,
+            _ => Some(RefCell::new(
+                WorkerPool::new(&options, &build, &files).context("creating workers")?,
+            )),
+        };
 
-        if files.is_empty() {
-            bail!("Did not find any files for path {}", path.display());
-        }
+        let cache =
+            BuildCache::new(options.cache_file.as_deref()).context("loading build cache")?;
 
-        if options.rustc && files.len() > 1 {
-            bail!("Found more than one file. --rustc only works with a single file.");
-        }
+        let history = match &options.history_branch {
+            Some(branch) => Some(RefCell::new(
+                History::new(&options, branch, &files).context("setting up git history")?,
+            )),
+            None => None,
+        };
+
+        let patches = match &options.emit_patches {
+            Some(dir) => Some(PatchSeries::new(&options, dir).context("setting up patch series")?),
+            None => None,
+        };
+
+        let resume = match &options.state_file {
+            Some(path) if options.resume => {
+                let checkpoint = Checkpoint::load(path)?;
+                info!(
+                    "Resuming in {} for {}",
+                    checkpoint.pass_name,
+                    checkpoint.file.display()
+                );
+                restore_file(&files, &checkpoint)?;
+                Some(checkpoint)
+            }
+            _ => None,
+        };
 
         Ok(Self {
             files,
             build,
+            workers,
+            cache: RefCell::new(cache),
+            history,
+            patches,
+            resume: RefCell::new(resume),
+            pass_index: Cell::new(0),
+            current_pass: Cell::new(None),
+            committed_changes: Cell::new(0),
+            progress: Progress::new(),
+            statistics,
+            events: Events::new(options.message_format),
+            review: options.interactive.then(|| Review::new(options.no_color)),
             options,
             cancel,
         })
@@ -152,22 +241,226 @@ impl Minimizer {
         &self,
         passes: impl IntoIterator<Item = Box<dyn Pass + 'a>>,
     ) -> Result<()> {
-        let inital_build = self.build.build()?;
+        let inital_build = self.build_cached()?;
         info!("Initial build: {inital_build}");
+        self.check_cancelled()?;
+        self.events.emit(Event::build(None, None, &inital_build));
         inital_build.require_reproduction("Initial")?;
 
         for mut pass in passes {
+            let pass_index = self.next_pass_index();
             if !self.pass_enabled(pass.name()) {
                 continue;
             }
-            self.run_pass(&mut *pass)?;
+            if self
+                .resume_pass_index()
+                .is_some_and(|resume| resume > pass_index)
+            {
+                info!("Skipping {}, it has finished before", pass.name());
+                continue;
+            }
+            self.current_pass.set(Some(pass.name()));
+            self.events.emit(Event::PassStart { pass: pass.name() });
+            let start = Instant::now();
+            let result = self.run_pass(&mut *pass, pass_index);
+            self.statistics.pass_finished(pass.name(), start.elapsed());
+            result?;
+            self.events.emit(Event::PassFinish {
+                pass: pass.name(),
+                duration: start.elapsed(),
+            });
+            self.current_pass.set(None);
+        }
+
+        Ok(())
+    }
+
+    /// Returns an error if the run has been cancelled. This must be checked after every build,
+    /// as the build has probably been interrupted and its result can't be trusted.
+    fn check_cancelled(&self) -> Result<()> {
+        if self.cancel.load(Ordering::SeqCst) {
+            let finished_passes = match self.current_pass.get() {
+                Some(_) => self.pass_index.get() - 1,
+                None => self.pass_index.get(),
+            };
+            return Err(Cancelled {
+                pass: self.current_pass.get().map(ToOwned::to_owned),
+                finished_passes,
+                committed_changes: self.committed_changes.get(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// The options that the passes see, which can be changed for a single pass.
+    pub(crate) fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    /// Builds the result a few more times to check that it really reproduces.
+    pub(crate) fn check_final(&self, runs: usize) -> Result<()> {
+        let result = self.build.build_runs(runs, false)?;
+        info!("Final check: {result}");
+        self.check_cancelled()?;
+        ensure!(
+            result.reproduces_issue(),
+            "the final result does not reproduce with {runs} runs"
+        );
+        Ok(())
+    }
+
+    /// The statistics of the run so far.
+    pub(crate) fn summary(&self) -> Summary {
+        self.statistics.summary(&self.files)
+    }
+
+    /// The number of changes that were committed over all passes so far.
+    pub(crate) fn committed_changes(&self) -> usize {
+        self.committed_changes.get()
+    }
+
+    /// Whether there is a checkpoint left that we want to resume from.
+    pub(crate) fn is_resuming(&self) -> bool {
+        self.resume.borrow().is_some()
+    }
+
+    /// Keeps a change that reproduces the issue. If it can't be added to the git history or the patches,
+    /// it is rolled back, so that the files never contain a change that isn't recorded.
+    fn commit_change(
+        &self,
+        change: FileChange<'_, '_>,
+        file: &SourceFile,
+        pass: &str,
+        paths: &[String],
+    ) -> Result<()> {
+        if let Err(err) = self.record_commit(file, pass, paths, change.before_content().0) {
+            change.rollback()?;
+            return Err(err);
         }
+        change.commit();
+        Ok(())
+    }
 
+    /// Adds a change to the git history and the patches, if there are any, and counts it.
+    /// `before` is the content of the file before the change.
+    fn record_commit(
+        &self,
+        file: &SourceFile,
+        pass: &str,
+        paths: &[String],
+        before: &str,
+    ) -> Result<()> {
+        if let Some(history) = &self.history {
+            history.borrow_mut().commit(file, pass, paths)?;
+        }
+        if let Some(patches) = &self.patches {
+            patches.write(file, pass, paths, before)?;
+        }
+        self.committed_changes.set(self.committed_changes.get() + 1);
+        self.events.emit(Event::Commit {
+            pass,
+            file: file.path_no_fs_interact(),
+            sites: paths,
+        });
         Ok(())
     }
 
-    fn run_pass(&self, pass: &mut dyn Pass) -> Result<()> {
+    /// With `--require-shrink`, changes that don't make the file smaller are rejected before building them.
+    fn shrinks(&self, pass: &dyn Pass, file: &SourceFile, before: &str, after: &str) -> bool {
+        if !self.options.require_shrink || pass.may_grow() {
+            return true;
+        }
+        let metric = self.options.size_metric;
+        let shrinks = metric.measure(after) < metric.measure(before);
+        if !shrinks {
+            if self.options.no_color {
+                info!("{file:?}: After {}: does not shrink", pass.name());
+            } else {
+                info!(
+                    "{file:?}: After {}: {}",
+                    pass.name(),
+                    "does not shrink".yellow()
+                );
+            }
+        }
+        shrinks
+    }
+
+    /// Whether a change that reproduces the issue is kept. With `--interactive`, the user decides.
+    fn accepted(&self, pass: &str, file: &SourceFile, before: &str, after: &str) -> Result<bool> {
+        match &self.review {
+            Some(review) => review.ask(pass, file, before, after),
+            None => Ok(true),
+        }
+    }
+
+    fn record_rollback(&self, file: &SourceFile, pass: &str, paths: &[String]) {
+        self.events.emit(Event::Rollback {
+            pass,
+            file: file.path_no_fs_interact(),
+            sites: paths,
+        });
+    }
+
+    /// Emits the result of a build of a change to the file.
+    fn record_build(&self, file: &SourceFile, pass: &str, result: &BuildResult) {
+        self.events.emit(Event::build(
+            Some(pass),
+            Some(file.path_no_fs_interact()),
+            result,
+        ));
+    }
+
+    /// Counts builds that have been run, not counting results from the cache.
+    fn builds_finished(&self, builds: usize) {
+        self.progress.builds_finished(builds);
+        if let Some(pass) = self.current_pass.get() {
+            self.statistics.builds_finished(pass, builds);
+        }
+    }
+
+    /// Returns the index of the current pass in the whole pipeline and advances to the next one.
+    fn next_pass_index(&self) -> usize {
+        let pass_index = self.pass_index.get();
+        self.pass_index.set(pass_index + 1);
+        pass_index
+    }
+
+    /// The index of the pass that the checkpoint we are resuming from was saved in, if it hasn't been reached yet.
+    fn resume_pass_index(&self) -> Option<usize> {
+        self.resume
+            .borrow()
+            .as_ref()
+            .map(|checkpoint| checkpoint.pass_index)
+    }
+
+    fn run_pass(&self, pass: &mut dyn Pass, pass_index: usize) -> Result<()> {
+        let mut resume = self
+            .resume
+            .borrow_mut()
+            .take_if(|checkpoint| checkpoint.pass_index == pass_index);
         let mut invalidated_files = HashSet::new();
+        if let Some(checkpoint) = &resume {
+            if checkpoint.pass_name != pass.name() {
+                bail!(
+                    "the state file was saved in {}, but this pass is {}. Were the passes changed?",
+                    checkpoint.pass_name,
+                    pass.name()
+                );
+            }
+            invalidated_files = self
+                .files
+                .iter()
+                .filter(|file| {
+                    checkpoint
+                        .invalidated_files
+                        .iter()
+                        .any(|path| path == file.path_no_fs_interact())
+                })
+                .collect();
+        }
+
         let mut refresh_and_try_again = false;
         loop {
             let span = info_span!("Starting round of pass", name = pass.name());
@@ -178,7 +471,40 @@ impl Minimizer {
                 if invalidated_files.contains(file) {
                     continue;
                 }
-                self.process_file(pass, file, &mut invalidated_files, &mut changes)?;
+
+                let checker = match resume
+                    .take_if(|checkpoint| checkpoint.file.as_path() == file.path_no_fs_interact())
+                {
+                    Some(checkpoint) => {
+                        // We don't know whether the files before this one were changed in this round.
+                        changes.mark_changed();
+                        let mut checker =
+                            PassController::resume(self.options.clone(), checkpoint.controller);
+                        if checker.is_finished() {
+                            continue;
+                        }
+                        checker
+                    }
+                    // The files before the one we are resuming in have already been processed in this round.
+                    None if resume.is_some() => continue,
+                    None => PassController::new(self.options.clone()),
+                };
+
+                self.process_file(
+                    pass,
+                    pass_index,
+                    file,
+                    checker,
+                    &mut invalidated_files,
+                    &mut changes,
+                )?;
+            }
+
+            if let Some(checkpoint) = &resume {
+                bail!(
+                    "the file {} from the state file was not found",
+                    checkpoint.file.display()
+                );
             }
 
             if !changes.had_changes() {
@@ -199,11 +525,39 @@ impl Minimizer {
         }
     }
 
-    #[instrument(skip(self, pass, invalidated_files, changes), fields(pass = %pass.name()), level = "debug")]
+    /// Saves the progress to the state file, if there is one.
+    fn save_checkpoint<'file>(
+        &self,
+        pass: &dyn Pass,
+        pass_index: usize,
+        file: &SourceFile,
+        checker: &PassController,
+        invalidated_files: impl Iterator<Item = &'file SourceFile>,
+    ) -> Result<()> {
+        let Some(path) = &self.options.state_file else {
+            return Ok(());
+        };
+
+        Checkpoint {
+            pass_index,
+            pass_name: pass.name().to_owned(),
+            file: file.path_no_fs_interact().to_owned(),
+            file_content: file.content_str(),
+            controller: checker.checkpoint(),
+            invalidated_files: invalidated_files
+                .map(|file| file.path_no_fs_interact().to_owned())
+                .collect(),
+        }
+        .save(path)
+    }
+
+    #[instrument(skip(self, pass, checker, invalidated_files, changes), fields(pass = %pass.name()), level = "debug")]
     fn process_file<'file>(
         &self,
         pass: &mut dyn Pass,
+        pass_index: usize,
         file: &'file SourceFile,
+        mut checker: PassController,
         invalidated_files: &mut HashSet<&'file SourceFile>,
         changes: &mut Changes,
     ) -> Result<()> {
@@ -212,57 +566,413 @@ impl Minimizer {
         // For this, we repeatedly try to apply a pass to a subset of a file until we've exhausted all options.
         // The logic for bisecting down lives in PassController.
 
-        let mut checker = PassController::new(self.options.clone());
         let mut initial_pass = true;
         loop {
-            let mut change = file.try_change(changes)?;
-            let (_, krate) = change.before_content();
-            let mut krate = krate.clone();
-            let has_made_change = pass.process_file(&mut krate, file, &mut checker);
-
-            match has_made_change {
-                ProcessState::Changed | ProcessState::FileInvalidated => {
-                    change.write(krate)?;
-
-                    let after = self.build.build()?;
-                    info!("{file:?}: After {}: {after}", pass.name());
-
-                    if after.reproduces_issue() {
-                        change.commit();
-                        checker.reproduces();
-                        if has_made_change == ProcessState::FileInvalidated {
+            if let Some(workers) = &self.workers
+                && checker.is_bisecting()
+            {
+                let file_invalidated =
+                    self.process_speculatively(pass, file, workers, &mut checker, changes)?;
+                if file_invalidated {
+                    invalidated_files.insert(file);
+                }
+                self.save_checkpoint(
+                    pass,
+                    pass_index,
+                    file,
+                    &checker,
+                    invalidated_files.iter().copied(),
+                )?;
+                if file_invalidated {
+                    break;
+                }
+            } else {
+                let mut change = file.try_change(changes)?;
+                let (_, krate) = change.before_content();
+                let mut krate = krate.clone();
+                let has_made_change = pass.process_file(&mut krate, file, &mut checker);
+
+                match has_made_change {
+                    ProcessState::Changed | ProcessState::FileInvalidated => {
+                        let paths = checker.current_paths();
+                        self.events.emit(Event::Batch {
+                            pass: pass.name(),
+                            file: file.path_no_fs_interact(),
+                            sites: &paths,
+                        });
+                        change.write(krate)?;
+
+                        let shrinks = self.shrinks(
+                            pass,
+                            file,
+                            change.before_content().0,
+                            &file.content_str(),
+                        );
+                        let accepted = if shrinks {
+                            let after = match self.build_cached() {
+                                Ok(after) => after,
+                                Err(err) => {
+                                    change.rollback()?;
+                                    return Err(err);
+                                }
+                            };
+                            info!("{file:?}: After {}: {after}", pass.name());
+                            self.record_build(file, pass.name(), &after);
+
+                            let accepted = if after.reproduces_issue() {
+                                self.accepted(
+                                    pass.name(),
+                                    file,
+                                    change.before_content().0,
+                                    &file.content_str(),
+                                )
+                            } else {
+                                Ok(false)
+                            };
+                            match accepted {
+                                Ok(accepted) => accepted,
+                                Err(err) => {
+                                    change.rollback()?;
+                                    return Err(err);
+                                }
+                            }
+                        } else {
+                            false
+                        };
+                        let file_invalidated = if accepted {
+                            self.commit_change(change, file, pass.name(), &paths)?;
+                            checker.reproduces();
+                            has_made_change == ProcessState::FileInvalidated
+                        } else {
+                            change.rollback()?;
+                            self.record_rollback(file, pass.name(), &paths);
+                            checker.does_not_reproduce();
+                            false
+                        };
+
+                        if file_invalidated {
                             invalidated_files.insert(file);
+                        }
+                        self.save_checkpoint(
+                            pass,
+                            pass_index,
+                            file,
+                            &checker,
+                            invalidated_files.iter().copied(),
+                        )?;
+                        if file_invalidated {
                             break;
                         }
-                    } else {
-                        change.rollback()?;
-                        checker.does_not_reproduce();
                     }
-                }
-                ProcessState::NoChange => {
-                    if !initial_pass {
-                        if self.options.no_color {
-                            info!("{file:?}: After {}: no changes", pass.name());
-                        } else {
-                            info!("{file:?}: After {}: {}", pass.name(), "no changes".yellow());
+                    ProcessState::NoChange => {
+                        if !initial_pass {
+                            if self.options.no_color {
+                                info!("{file:?}: After {}: no changes", pass.name());
+                            } else {
+                                info!("{file:?}: After {}: {}", pass.name(), "no changes".yellow());
+                            }
                         }
+                        checker.no_change();
                     }
-                    checker.no_change();
                 }
             }
             initial_pass = false;
 
-            if self.cancel.load(Ordering::SeqCst) {
-                info!("Exiting early.");
-                std::process::exit(0);
-            }
+            self.check_cancelled()?;
+
+            self.progress.report(
+                pass.name(),
+                file,
+                checker.sets_left(),
+                &self.files,
+                &self.statistics,
+            );
 
             if checker.is_finished() {
                 break;
             }
         }
+        self.statistics.file_finished(pass.name(), checker.sites());
         Ok(())
     }
+
+    /// Builds the current state of the files, unless that state has been built before.
+    fn build_cached(&self) -> Result<BuildResult> {
+        let key = self.cache_key(None);
+        let cached = self.cache.borrow().get(key);
+        if let Some(reproduces) = cached {
+            return Ok(self.build.cached_result(reproduces));
+        }
+
+        let result = self.build.build()?;
+        self.builds_finished(1);
+        // An interrupted build says nothing about the issue, so it must not end up in the cache.
+        self.check_cancelled()?;
+        self.cache
+            .borrow_mut()
+            .insert(key, result.reproduces_issue())?;
+        Ok(result)
+    }
+
+    /// The cache key for the current state of the files, optionally with one file replaced by new content.
+    fn cache_key(&self, replaced: Option<(&SourceFile, &str)>) -> CacheKey {
+        let contents = self
+            .files
+            .iter()
+            .map(|file| match replaced {
+                Some((replaced, content)) if replaced == file => content.to_owned(),
+                _ => file.content_str(),
+            })
+            .collect::<Vec<_>>();
+
+        CacheKey::new(
+            self.build.fingerprint(),
+            self.files
+                .iter()
+                .map(|file| self.build.cache_path(file.path_no_fs_interact()))
+                .zip(contents.iter().map(String::as_str)),
+        )
+    }
+
+    /// Tries several candidate sets of the bisection at the same time, each in its own worker.
+    /// The first one that reproduces is committed. Returns whether that has invalidated the file.
+    fn process_speculatively(
+        &self,
+        pass: &mut dyn Pass,
+        file: &SourceFile,
+        workers: &RefCell<WorkerPool>,
+        checker: &mut PassController,
+        changes: &mut Changes,
+    ) -> Result<bool> {
+        let mut workers = workers.borrow_mut();
+        let file_index = self
+            .files
+            .iter()
+            .position(|other| other == file)
+            .expect("processing unknown file");
+
+        let mut speculations = checker.speculate(workers.len());
+
+        let mut change = file.try_change(changes)?;
+        let (_, krate) = change.before_content();
+        let mut attempts = speculations
+            .iter_mut()
+            .map(|speculation| {
+                let mut krate = krate.clone();
+                let has_made_change = pass.process_file(&mut krate, file, speculation);
+                (has_made_change, krate)
+            })
+            .collect::<Vec<_>>();
+
+        // Candidates that don't change anything can't reproduce anything new, so they don't need a build.
+        let candidates = attempts
+            .iter()
+            .enumerate()
+            .filter(|(_, (has_made_change, _))| *has_made_change != ProcessState::NoChange)
+            .map(|(i, (_, krate))| Ok((i, file.render(krate)?)))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            // Candidates that don't shrink the file don't need a build either.
+            .filter(|(_, content)| self.shrinks(pass, file, change.before_content().0, content))
+            .collect::<Vec<_>>();
+
+        let paths = speculations
+            .iter()
+            .map(PassController::current_paths)
+            .collect::<Vec<_>>();
+        for (i, _) in &candidates {
+            self.events.emit(Event::Batch {
+                pass: pass.name(),
+                file: file.path_no_fs_interact(),
+                sites: &paths[*i],
+            });
+        }
+
+        let keys = candidates
+            .iter()
+            .map(|(_, content)| self.cache_key(Some((file, content))))
+            .collect::<Vec<_>>();
+        let mut results = keys
+            .iter()
+            .map(|&key| {
+                let reproduces = self.cache.borrow().get(key);
+                reproduces.map(|reproduces| self.build.cached_result(reproduces))
+            })
+            .collect::<Vec<_>>();
+
+        let uncached = (0..candidates.len())
+            .filter(|&i| results[i].is_none())
+            .collect::<Vec<_>>();
+        let built = workers.build_all(
+            &self.files,
+            &uncached
+                .iter()
+                .map(|&i| (file_index, candidates[i].1.clone()))
+                .collect::<Vec<_>>(),
+        )?;
+        self.builds_finished(built.len());
+        self.check_cancelled()?;
+        for (i, after) in uncached.into_iter().zip(built) {
+            self.cache
+                .borrow_mut()
+                .insert(keys[i], after.reproduces_issue())?;
+            results[i] = Some(after);
+        }
+
+        let mut reproduces = vec![false; attempts.len()];
+        for ((i, _), after) in candidates.iter().zip(results) {
+            let after = after.expect("all candidates have been built");
+            info!("{file:?}: After {} (job {i}): {after}", pass.name());
+            self.record_build(file, pass.name(), &after);
+            reproduces[*i] = after.reproduces_issue();
+        }
+
+        // Rejected candidates count as not reproducing. Only the first accepted one is committed.
+        for (i, content) in &candidates {
+            if reproduces[*i] {
+                reproduces[*i] =
+                    self.accepted(pass.name(), file, change.before_content().0, content)?;
+                if reproduces[*i] {
+                    break;
+                }
+            }
+        }
+
+        let first_reproducing = reproduces.iter().position(|&reproduces| reproduces);
+        // Candidates after the committed one are tried again, so only the ones before it have failed.
+        for (i, _) in &candidates {
+            if first_reproducing.is_some_and(|first| *i > first) {
+                break;
+            }
+            if !reproduces[*i] {
+                self.record_rollback(file, pass.name(), &paths[*i]);
+            }
+        }
+
+        let mut file_invalidated = false;
+        if let Some(i) = first_reproducing {
+            let (has_made_change, krate) = attempts.swap_remove(i);
+            change.write(krate)?;
+            self.commit_change(change, file, pass.name(), &paths[i])?;
+            file_invalidated = has_made_change == ProcessState::FileInvalidated;
+        }
+
+        checker.speculation_results(speculations, &reproduces);
+
+        Ok(file_invalidated)
+    }
+}
+
+/// Whether the pass is selected with `--passes`.
+fn pass_enabled(options: &Options, name: &str) -> bool {
+    match &options.passes {
+        None => true,
+        Some(PassSelection::Enable(v)) => v.iter().any(|allowed| name == allowed),
+        Some(PassSelection::Disable(v)) => v.iter().all(|forbidden| name != forbidden),
+    }
+}
+
+/// Finds and parses all Rust files in `--path`, except for the ignored ones.
+pub(crate) fn collect_files(options: &Options) -> Result<Vec<SourceFile>> {
+    let path = &options.path;
+    let walk = walkdir::WalkDir::new(path);
+
+    let files = walk
+        .into_iter()
+        .filter_map(|entry| match entry {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                warn!("Error during walkdir: {err}");
+                None
+            }
+        })
+        .filter(|entry| entry.path().extension() == Some(OsStr::new("rs")))
+        .filter(|entry| {
+            if options
+                .ignore_file
+                .iter()
+                .any(|ignored| entry.path().starts_with(ignored))
+            {
+                info!("Ignoring file: {}", entry.path().display());
+                false
+            } else {
+                true
+            }
+        })
+        .map(|entry| {
+            SourceFile::open(
+                entry.into_path(),
+                options.formatter,
+                options.preserve_formatting,
+            )
+        })
+        .inspect(|file| {
+            if let Ok(file) = file {
+                info!("Collecting file: {file:?}");
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if files.is_empty() {
+        bail!("Did not find any files for path {}", path.display());
+    }
+
+    if options.rustc && files.len() > 1 {
+        bail!("Found more than one file. --rustc only works with a single file.");
+    }
+
+    Ok(files)
+}
+
+/// Runs only the initial collection of the pass on every file and reports the candidates it finds,
+/// without building or changing anything.
+pub(crate) fn dry_run_pass(files: &[SourceFile], options: &Options, name: &str, events: Events) {
+    if !pass_enabled(options, name) {
+        info!("{name}: disabled");
+        return;
+    }
+    let Some(mut pass) = crate::passes::by_name(name) else {
+        info!("{name}: needs a build to find candidates");
+        return;
+    };
+
+    for file in files {
+        let mut checker = PassController::new(options.clone());
+        // The changes that the pass makes to the copy are thrown away.
+        pass.process_file(&mut file.krate(), file, &mut checker);
+
+        let candidates = checker.current_paths();
+        info!("{name}: {file:?}: {} candidates", candidates.len());
+        for candidate in &candidates {
+            info!("  {candidate}");
+        }
+        events.emit(Event::Candidates {
+            pass: name,
+            file: file.path_no_fs_interact(),
+            sites: &candidates,
+        });
+    }
+}
+
+/// Restores the file that the checkpoint was saved for, in case the run was interrupted while trying a change.
+fn restore_file(files: &[SourceFile], checkpoint: &Checkpoint) -> Result<()> {
+    let Some(file) = files
+        .iter()
+        .find(|file| file.path_no_fs_interact() == checkpoint.file)
+    else {
+        bail!(
+            "the file {} from the state file was not found",
+            checkpoint.file.display()
+        );
+    };
+
+    if file.content_str() != checkpoint.file_content {
+        info!("{file:?}: Restoring last committed content");
+        let krate = syn::parse_file(&checkpoint.file_content)
+            .with_context(|| format!("parsing saved content of {file:?}"))?;
+        file.restore(checkpoint.file_content.clone(), krate)?;
+    }
+    Ok(())
 }
 
 macro_rules! tracking {
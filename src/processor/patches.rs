@@ -0,0 +1,109 @@
+//! Handles `--emit-patches`, which writes every committed change as a numbered patch.
+
+use anyhow::{Context, Result, bail};
+use std::{
+    cell::Cell,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    SourceFile,
+    diff::unified_diff,
+    files::{project_root, relative_path},
+    history::message,
+};
+use crate::Options;
+
+/// A directory with one patch per change, which can be applied in order with `git apply` or `patch -p1`
+/// in the project directory.
+#[derive(Debug)]
+pub(crate) struct PatchSeries {
+    dir: PathBuf,
+    /// The directory that the paths in the patches are relative to.
+    root: PathBuf,
+    next: Cell<usize>,
+}
+
+impl PatchSeries {
+    /// The directory must be empty, unless we are resuming. Then the series is continued.
+    pub(crate) fn new(options: &Options, dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        let existing = fs::read_dir(dir)
+            .with_context(|| format!("reading {}", dir.display()))?
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .is_ok_and(|entry| entry.path().extension().is_some_and(|ext| ext == "patch"))
+            })
+            .count();
+        if existing > 0 && !options.resume {
+            bail!(
+                "the patch directory {} already contains patches",
+                dir.display()
+            );
+        }
+
+        let root = project_root(options)?;
+
+        info!("Writing a patch for every change to {}", dir.display());
+
+        Ok(Self {
+            dir: dir.to_owned(),
+            root,
+            next: Cell::new(existing + 1),
+        })
+    }
+
+    /// Writes the patch for a change that has just been committed. `before` is the content of the file
+    /// before the change.
+    pub(crate) fn write(
+        &self,
+        file: &SourceFile,
+        pass: &str,
+        paths: &[String],
+        before: &str,
+    ) -> Result<()> {
+        let path = file.path_no_fs_interact();
+        // Files outside of the project keep their full path.
+        let relative =
+            relative_path(&self.root, path).unwrap_or_else(|_| path.to_string_lossy().into_owned());
+
+        let number = self.next.get();
+        let name = patch_name(number, pass, &relative);
+        let patch = format!(
+            "{}\n{}",
+            message(pass, &relative, paths),
+            unified_diff(&relative, before, &file.content_str())
+        );
+
+        let patch_path = self.dir.join(name);
+        fs::write(&patch_path, patch)
+            .with_context(|| format!("writing patch {}", patch_path.display()))?;
+        self.next.set(number + 1);
+        Ok(())
+    }
+}
+
+/// The file name of a patch, like `0003-item-deleter-src-main.rs.patch`.
+fn patch_name(number: usize, pass: &str, path: &str) -> String {
+    let path = path.trim_start_matches('/').replace(['/', '\\', ':'], "-");
+    format!("{number:04}-{pass}-{path}.patch")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::patch_name;
+
+    #[test]
+    fn names_are_sorted_and_flat() {
+        assert_eq!(
+            patch_name(3, "item-deleter", "src/main.rs"),
+            "0003-item-deleter-src-main.rs.patch"
+        );
+        assert_eq!(
+            patch_name(12, "privatize", "/tmp/lib.rs"),
+            "0012-privatize-tmp-lib.rs.patch"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Regularly reports how far the minimization has come, so that long runs can be told apart from stuck ones.
+
+use std::{
+    cell::Cell,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use super::{
+    SourceFile,
+    summary::{FormatDuration, Size, Statistics},
+};
+
+/// How often the progress is reported at most.
+const REPORT_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug)]
+pub(crate) struct Progress {
+    last_report: Cell<Instant>,
+    builds: Cell<usize>,
+}
+
+impl Progress {
+    pub(crate) fn new() -> Self {
+        Self {
+            last_report: Cell::new(Instant::now()),
+            builds: Cell::new(0),
+        }
+    }
+
+    /// Records that builds have been run, not counting results from the cache.
+    pub(crate) fn builds_finished(&self, builds: usize) {
+        self.builds.set(self.builds.get() + builds);
+    }
+
+    /// Logs the progress, unless that has happened recently. `sets_left` is the number of candidate
+    /// sets that the bisection in `file` still has to try.
+    pub(crate) fn report(
+        &self,
+        pass: &str,
+        file: &SourceFile,
+        sets_left: Option<usize>,
+        files: &[SourceFile],
+        statistics: &Statistics,
+    ) {
+        let now = Instant::now();
+        if now.duration_since(self.last_report.get()) < REPORT_INTERVAL {
+            return;
+        }
+        self.last_report.set(now);
+
+        let size = Size::of(files);
+        let initial = statistics.before();
+        let elapsed = now.duration_since(statistics.start_time());
+        let builds = self.builds.get();
+        let builds_per_minute = builds as f64 / elapsed.as_secs_f64() * 60.0;
+
+        let mut message = format!(
+            "Progress: {pass}, {} lines ({}), {} tokens ({}), {builds} builds in {} ({builds_per_minute:.1}/min)",
+            size.lines,
+            Reduction(initial.lines, size.lines),
+            size.tokens,
+            Reduction(initial.tokens, size.tokens),
+            FormatDuration(elapsed),
+        );
+        if let Some(sets_left) = sets_left {
+            message.push_str(&format!(", {sets_left} sets left for {file:?}"));
+            // Every set needs one build, at least until it fails and is split up. This only covers the
+            // current file and pass, the later ones can't be estimated.
+            if builds > 0 {
+                let eta = elapsed.mul_f64(sets_left as f64 / builds as f64);
+                message.push_str(&format!(" (about {} for this file)", FormatDuration(eta)));
+            }
+        }
+        info!("{message}");
+    }
+}
+
+/// How much smaller something got, as a percentage.
+struct Reduction(usize, usize);
+
+impl Display for Reduction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Reduction(before, after) = *self;
+        if before == 0 {
+            return f.write_str("-0%");
+        }
+        let percent = (before.saturating_sub(after)) as f64 / before as f64 * 100.0;
+        write!(f, "-{percent:.0}%")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reduction;
+
+    #[test]
+    fn formats_reduction() {
+        assert_eq!(Reduction(200, 50).to_string(), "-75%");
+        assert_eq!(Reduction(0, 0).to_string(), "-0%");
+    }
+}
@@ -1,6 +1,6 @@
 //! Deletes dead code.
 
-use crate::build::Build;
+use crate::{build::Build, events::Event};
 
 use super::{Minimizer, Pass, PassController, ProcessState, SourceFile, files::Changes, tracking};
 use anyhow::{Context, Result};
@@ -14,16 +14,27 @@ fn file_for_suggestion(suggestion: &Suggestion) -> &Path {
     Path::new(&suggestion.solutions[0].replacements[0].snippet.file_name)
 }
 
-const PASS_NAME: &str = "delete-unused-functions";
+pub(crate) const PASS_NAME: &str = "delete-unused-functions";
+
+/// The name for the removal of unused imports, which always happens before deleting unused functions.
+const IMPORTS_PASS_NAME: &str = "unused-imports";
 
 impl Minimizer {
     pub fn delete_dead_code(&mut self) -> Result<()> {
-        if !self.pass_enabled(PASS_NAME) {
+        let pass_index = self.pass_index.get();
+        if !self.pass_enabled(PASS_NAME)
+            || self
+                .resume_pass_index()
+                .is_some_and(|resume| resume > pass_index)
+        {
+            // `run_passes` isn't called, so it can't count the pass.
+            self.next_pass_index();
             return Ok(());
         }
 
         let inital_build = self.build.build()?;
         info!("Before reaper: {inital_build}");
+        self.check_cancelled()?;
 
         inital_build.require_reproduction("Initial")?;
 
@@ -43,7 +54,10 @@ impl Minimizer {
         }
 
         // Always unconditionally apply unused imports.
-        self.apply_unused_imports(&suggestions_for_file)?;
+        // When resuming in the middle of deleting unused functions, this has happened before already.
+        if self.resume_pass_index() != Some(pass_index) {
+            self.apply_unused_imports(&suggestions_for_file)?;
+        }
 
         self.run_passes([
             Box::new(DeleteUnusedFunctions::new(self.build.clone(), diags)) as Box<dyn Pass>,
@@ -87,16 +101,46 @@ impl Minimizer {
             );
 
             let result = syn::parse_file(&result).context("parsing file after rustfix")?;
+            self.events.emit(Event::Batch {
+                pass: IMPORTS_PASS_NAME,
+                file: file.path_no_fs_interact(),
+                sites: &[],
+            });
             change.write(result)?;
 
-            let after = self.build.build()?;
+            let after = match self.build_cached() {
+                Ok(after) => after,
+                Err(err) => {
+                    change.rollback()?;
+                    return Err(err);
+                }
+            };
 
             info!("{file:?}: After reaper: {after}");
-
-            if after.reproduces_issue() {
-                change.commit();
+            self.record_build(file, IMPORTS_PASS_NAME, &after);
+
+            let accepted = if after.reproduces_issue() {
+                self.accepted(
+                    IMPORTS_PASS_NAME,
+                    file,
+                    change.before_content().0,
+                    &file.content_str(),
+                )
+            } else {
+                Ok(false)
+            };
+            let accepted = match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    change.rollback()?;
+                    return Err(err);
+                }
+            };
+            if accepted {
+                self.commit_change(change, file, IMPORTS_PASS_NAME, &[])?;
             } else {
                 change.rollback()?;
+                self.record_rollback(file, IMPORTS_PASS_NAME, &[]);
             }
         }
 
@@ -0,0 +1,80 @@
+//! Handles `--interactive`, where the user decides whether a change that reproduces the issue is kept.
+
+use anyhow::{Context, Result, bail};
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+};
+
+use super::{
+    SourceFile,
+    diff::{colored, unified_diff},
+};
+
+#[derive(Debug)]
+pub(crate) struct Review {
+    /// The passes whose changes are kept without asking.
+    always_accept: RefCell<Vec<String>>,
+    no_color: bool,
+}
+
+impl Review {
+    pub(crate) fn new(no_color: bool) -> Self {
+        Self {
+            always_accept: RefCell::new(Vec::new()),
+            no_color,
+        }
+    }
+
+    /// Shows the diff of the change and asks whether it should be kept. Returns `false` if it was rejected.
+    pub(crate) fn ask(
+        &self,
+        pass: &str,
+        file: &SourceFile,
+        before: &str,
+        after: &str,
+    ) -> Result<bool> {
+        if self
+            .always_accept
+            .borrow()
+            .iter()
+            .any(|accepted| accepted == pass)
+        {
+            return Ok(true);
+        }
+
+        let diff = unified_diff(
+            &file.path_no_fs_interact().display().to_string(),
+            before,
+            after,
+        );
+        if self.no_color {
+            eprintln!("{diff}");
+        } else {
+            eprintln!("{}", colored(&diff));
+        }
+
+        loop {
+            eprint!("{pass}: Keep this change? [y]es, [n]o, [a]lways for this pass: ");
+            io::stderr().flush().context("writing prompt")?;
+
+            let mut answer = String::new();
+            let read = io::stdin()
+                .read_line(&mut answer)
+                .context("reading answer")?;
+            if read == 0 {
+                bail!("stdin was closed while waiting for an answer");
+            }
+
+            match answer.trim() {
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                "a" | "always" => {
+                    self.always_accept.borrow_mut().push(pass.to_owned());
+                    return Ok(true);
+                }
+                _ => eprintln!("Please answer y, n or a"),
+            }
+        }
+    }
+}
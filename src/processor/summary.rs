@@ -0,0 +1,339 @@
+//! Statistics about a run, to see which passes are worth running for a kind of issue.
+
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use proc_macro2::TokenTree;
+use quote::ToTokens;
+use serde::Serialize;
+use syn::visit::Visit;
+
+use super::{SourceFile, checker::SiteCounts};
+
+/// The size of the code that is being minimized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Size {
+    pub lines: usize,
+    pub tokens: usize,
+    /// Items including nested ones, associated items and foreign items.
+    pub items: usize,
+    /// The files that still contain any code.
+    pub files: usize,
+}
+
+impl Size {
+    pub(crate) fn of(files: &[SourceFile]) -> Self {
+        let mut size = Size::default();
+        for file in files {
+            let content = file.content_str();
+            // The content has been parsed by syn before, so this can only fail if it was changed outside.
+            let Ok(krate) = syn::parse_file(&content) else {
+                continue;
+            };
+            let tokens = count_tokens(krate.to_token_stream());
+
+            size.lines += content.lines().count();
+            size.tokens += tokens;
+            size.items += ItemCounter::count(&krate);
+            size.files += usize::from(tokens > 0);
+        }
+        size
+    }
+}
+
+/// How the size of a file is measured for `--require-shrink`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SizeMetric {
+    Bytes,
+    Lines,
+    #[default]
+    Tokens,
+    /// Items, statements, expressions, patterns and types.
+    Nodes,
+}
+
+impl SizeMetric {
+    pub(crate) fn measure(self, content: &str) -> usize {
+        match self {
+            SizeMetric::Bytes => content.len(),
+            SizeMetric::Lines => content.lines().count(),
+            SizeMetric::Tokens => content
+                .parse::<proc_macro2::TokenStream>()
+                .map_or(0, count_tokens),
+            SizeMetric::Nodes => {
+                syn::parse_file(content).map_or(0, |krate| ItemCounter::count_nodes(&krate))
+            }
+        }
+    }
+}
+
+fn count_tokens(tokens: proc_macro2::TokenStream) -> usize {
+    tokens
+        .into_iter()
+        .map(|token| match token {
+            // The delimiters count as two tokens.
+            TokenTree::Group(group) => 2 + count_tokens(group.stream()),
+            _ => 1,
+        })
+        .sum()
+}
+
+/// Counts items including nested ones, associated items and foreign items. With `all_nodes`,
+/// statements, expressions, patterns and types are counted as well.
+#[derive(Default)]
+struct ItemCounter {
+    count: usize,
+    all_nodes: bool,
+}
+
+impl ItemCounter {
+    fn count(krate: &syn::File) -> usize {
+        Self::default().run(krate)
+    }
+
+    fn count_nodes(krate: &syn::File) -> usize {
+        let counter = Self {
+            count: 0,
+            all_nodes: true,
+        };
+        counter.run(krate)
+    }
+
+    fn run(mut self, krate: &syn::File) -> usize {
+        self.visit_file(krate);
+        self.count
+    }
+}
+
+impl<'ast> Visit<'ast> for ItemCounter {
+    fn visit_item(&mut self, item: &'ast syn::Item) {
+        self.count += 1;
+        syn::visit::visit_item(self, item);
+    }
+
+    fn visit_impl_item(&mut self, item: &'ast syn::ImplItem) {
+        self.count += 1;
+        syn::visit::visit_impl_item(self, item);
+    }
+
+    fn visit_trait_item(&mut self, item: &'ast syn::TraitItem) {
+        self.count += 1;
+        syn::visit::visit_trait_item(self, item);
+    }
+
+    fn visit_foreign_item(&mut self, item: &'ast syn::ForeignItem) {
+        self.count += 1;
+        syn::visit::visit_foreign_item(self, item);
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast syn::Stmt) {
+        self.count += usize::from(self.all_nodes);
+        syn::visit::visit_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &'ast syn::Expr) {
+        self.count += usize::from(self.all_nodes);
+        syn::visit::visit_expr(self, expr);
+    }
+
+    fn visit_pat(&mut self, pat: &'ast syn::Pat) {
+        self.count += usize::from(self.all_nodes);
+        syn::visit::visit_pat(self, pat);
+    }
+
+    fn visit_type(&mut self, ty: &'ast syn::Type) {
+        self.count += usize::from(self.all_nodes);
+        syn::visit::visit_type(self, ty);
+    }
+}
+
+impl Display for Size {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} lines, {} tokens, {} items, {} files",
+            self.lines, self.tokens, self.items, self.files
+        )
+    }
+}
+
+/// What a pass has done over the whole run. Passes that run several times are added up.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PassSummary {
+    pub name: String,
+    /// The minimization sites that the pass found.
+    pub candidates: usize,
+    /// The builds that were run, not counting results from the cache.
+    pub builds: usize,
+    /// The sites that could be applied while the issue still reproduced.
+    pub committed: usize,
+    /// The sites that stopped the issue from reproducing on their own.
+    pub failed: usize,
+    #[serde(rename = "duration_secs", serialize_with = "crate::events::secs")]
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    /// The passes in the order in which they first ran.
+    pub passes: Vec<PassSummary>,
+    pub before: Size,
+    pub after: Size,
+    #[serde(rename = "duration_secs", serialize_with = "crate::events::secs")]
+    pub duration: Duration,
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "Before: {}", self.before)?;
+        writeln!(f, "After:  {}", self.after)?;
+        writeln!(f, "Took {}", FormatDuration(self.duration))?;
+        write!(
+            f,
+            "{:<28} {:>10} {:>7} {:>9} {:>7} {:>8}",
+            "pass", "candidates", "builds", "committed", "failed", "time"
+        )?;
+        for pass in &self.passes {
+            write!(
+                f,
+                "\n{:<28} {:>10} {:>7} {:>9} {:>7} {:>8}",
+                pass.name,
+                pass.candidates,
+                pass.builds,
+                pass.committed,
+                pass.failed,
+                FormatDuration(pass.duration).to_string(),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the statistics while the passes run.
+#[derive(Debug)]
+pub(crate) struct Statistics {
+    start: Instant,
+    before: Size,
+    passes: RefCell<Vec<PassSummary>>,
+}
+
+impl Statistics {
+    /// Has to be created before anything is changed, the files are measured for the size before the run.
+    pub(crate) fn new(files: &[SourceFile]) -> Self {
+        Self {
+            start: Instant::now(),
+            before: Size::of(files),
+            passes: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn before(&self) -> Size {
+        self.before
+    }
+
+    pub(crate) fn start_time(&self) -> Instant {
+        self.start
+    }
+
+    fn with_pass(&self, pass: &str, f: impl FnOnce(&mut PassSummary)) {
+        let mut passes = self.passes.borrow_mut();
+        let index = match passes.iter().position(|summary| summary.name == pass) {
+            Some(index) => index,
+            None => {
+                passes.push(PassSummary {
+                    name: pass.to_owned(),
+                    ..PassSummary::default()
+                });
+                passes.len() - 1
+            }
+        };
+        f(&mut passes[index]);
+    }
+
+    pub(crate) fn builds_finished(&self, pass: &str, builds: usize) {
+        self.with_pass(pass, |summary| summary.builds += builds);
+    }
+
+    pub(crate) fn file_finished(&self, pass: &str, sites: SiteCounts) {
+        self.with_pass(pass, |summary| {
+            summary.candidates += sites.candidates;
+            summary.committed += sites.committed;
+            summary.failed += sites.failed;
+        });
+    }
+
+    pub(crate) fn pass_finished(&self, pass: &str, duration: Duration) {
+        self.with_pass(pass, |summary| summary.duration += duration);
+    }
+
+    pub(crate) fn summary(&self, files: &[SourceFile]) -> Summary {
+        let after = Size::of(files);
+        Summary {
+            passes: self.passes.borrow().clone(),
+            before: self.before,
+            after,
+            duration: self.start.elapsed(),
+        }
+    }
+}
+
+pub(crate) struct FormatDuration(pub(crate) Duration);
+
+impl Display for FormatDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = self.0.as_secs();
+        match secs {
+            0..60 => write!(f, "{secs}s"),
+            60..3600 => write!(f, "{}m{}s", secs / 60, secs % 60),
+            _ => write!(f, "{}h{}m", secs / 3600, secs / 60 % 60),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{FormatDuration, ItemCounter, SizeMetric, count_tokens};
+
+    #[test]
+    fn counts_tokens() {
+        let tokens = "fn main() { let x = 1; }".parse().unwrap();
+        // `fn main ( ) { let x = 1 ; }`
+        assert_eq!(count_tokens(tokens), 11);
+    }
+
+    #[test]
+    fn counts_nested_items() {
+        let krate = syn::parse_file(
+            "mod a { struct S; impl S { fn f() { fn g() {} } const C: u8 = 0; } } extern \"C\" { fn h(); }",
+        )
+        .unwrap();
+        // `mod a`, `struct S`, `impl S`, `fn f`, `fn g`, `const C`, the extern block and `fn h`.
+        assert_eq!(ItemCounter::count(&krate), 8);
+    }
+
+    #[test]
+    fn measures_sizes() {
+        let content = "fn main() {\n    let x = 1;\n}\n";
+        assert_eq!(SizeMetric::Bytes.measure(content), 29);
+        assert_eq!(SizeMetric::Lines.measure(content), 3);
+        assert_eq!(SizeMetric::Tokens.measure(content), 11);
+        // `fn main`, the `let` statement, the pattern `x` and the expression `1`.
+        assert_eq!(SizeMetric::Nodes.measure(content), 4);
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(FormatDuration(Duration::from_secs(42)).to_string(), "42s");
+        assert_eq!(FormatDuration(Duration::from_secs(125)).to_string(), "2m5s");
+        assert_eq!(
+            FormatDuration(Duration::from_secs(7500)).to_string(),
+            "2h5m"
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! Copies of the project that candidates can be built in at the same time.
+
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+use super::{
+    SourceFile,
+    files::{project_root, relative_path},
+};
+use crate::{
+    Options,
+    build::{Build, BuildResult},
+    scratch::copy_dir,
+};
+
+/// A pool of copies of the project. Every worker has its own copy with its own target directory,
+/// so that candidates can be built in parallel without touching the user's project.
+/// The copies only ever receive file contents, the `SourceFile`s stay the source of truth.
+#[derive(Debug)]
+pub(crate) struct WorkerPool {
+    workers: Vec<Worker>,
+    /// The paths of the source files relative to the project directory, in the same order as the files.
+    relative_paths: Vec<PathBuf>,
+}
+
+#[derive(Debug)]
+struct Worker {
+    dir: TempDir,
+    build: Build,
+    /// The contents that we last wrote to the copy, in the same order as the files.
+    synced: Vec<Option<String>>,
+}
+
+impl WorkerPool {
+    pub(crate) fn new(options: &Options, build: &Build, files: &[SourceFile]) -> Result<Self> {
+        let root = project_root(options)?;
+
+        let relative_paths = files
+            .iter()
+            .map(|file| {
+                let relative = relative_path(&root, file.path_no_fs_interact())
+                    .context("--jobs requires all files to be in the project directory")?;
+                Ok(PathBuf::from(relative))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let workers = (0..options.jobs)
+            .map(|i| {
+                let dir = tempfile::Builder::new()
+                    .prefix("cargo-minimize-worker")
+                    .tempdir()
+                    .context("creating worker directory")?;
+                copy_dir(&root, dir.path())
+                    .with_context(|| format!("copying project to {}", dir.path().display()))?;
+                info!("Created worker {i} in {}", dir.path().display());
+
+                let build = build.for_copy(&root, dir.path())?;
+
+                Ok(Worker {
+                    dir,
+                    build,
+                    synced: vec![None; files.len()],
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            workers,
+            relative_paths,
+        })
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Builds every candidate in its own worker at the same time. A candidate is the new content of the file
+    /// at the index into `files`, all other files get their current content.
+    pub(crate) fn build_all(
+        &mut self,
+        files: &[SourceFile],
+        candidates: &[(usize, String)],
+    ) -> Result<Vec<BuildResult>> {
+        assert!(candidates.len() <= self.workers.len());
+
+        for (worker, (file_index, content)) in self.workers.iter_mut().zip(candidates) {
+            for (i, file) in files.iter().enumerate() {
+                let content = if i == *file_index {
+                    content.clone()
+                } else {
+                    file.content_str()
+                };
+                worker.sync(&self.relative_paths[i], i, content)?;
+            }
+        }
+
+        std::thread::scope(|scope| {
+            let handles = self.workers[..candidates.len()]
+                .iter()
+                .map(|worker| scope.spawn(|| worker.build.build()))
+                .collect::<Vec<_>>();
+
+            handles
+                .into_iter()
+                .map(|handle| handle.join().expect("build thread panicked"))
+                .collect()
+        })
+    }
+}
+
+impl Worker {
+    fn sync(&mut self, relative_path: &Path, index: usize, content: String) -> Result<()> {
+        if self.synced[index].as_ref() == Some(&content) {
+            return Ok(());
+        }
+
+        let path = self.dir.path().join(relative_path);
+        fs::write(&path, &content).with_context(|| format!("writing file {}", path.display()))?;
+        self.synced[index] = Some(content);
+        Ok(())
+    }
+}
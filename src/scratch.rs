@@ -0,0 +1,126 @@
+//! Handles the --output flag, which minimizes a copy of the code instead of the user's files.
+
+use anyhow::{Context, Result, bail};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+use tempfile::TempDir;
+
+use crate::{Options, processor::project_root};
+
+/// A copy of the project (or only of the `--path`) that is minimized instead of the original.
+#[derive(Debug)]
+pub(crate) struct Scratch {
+    dir: TempDir,
+    output: PathBuf,
+    /// The options from before they were changed to point to the copy.
+    original: Options,
+}
+
+impl Scratch {
+    /// Copies the code and changes the options to point to the copy.
+    pub(crate) fn new(options: &mut Options, output: PathBuf) -> Result<Self> {
+        if output.try_exists()? && fs::read_dir(&output)?.next().is_some() {
+            bail!("output directory {} is not empty", output.display());
+        }
+        let original = options.clone();
+
+        let root = project_root(options)?;
+        let path = options
+            .path
+            .canonicalize()
+            .with_context(|| format!("canonicalizing {}", options.path.display()))?;
+
+        let dir = tempfile::Builder::new()
+            .prefix("cargo-minimize-scratch")
+            .tempdir()
+            .context("creating scratch directory")?;
+
+        match path.strip_prefix(&root) {
+            Ok(relative) => {
+                copy_dir(&root, dir.path())?;
+                options.path = dir.path().join(relative);
+                options.project_dir = Some(dir.path().to_owned());
+
+                for ignore_file in &mut options.ignore_file {
+                    if let Ok(ignored) = ignore_file.canonicalize()
+                        && let Ok(relative) = ignored.strip_prefix(&root)
+                    {
+                        *ignore_file = dir.path().join(relative);
+                    }
+                }
+            }
+            // The code lives somewhere else, for example a single file for `--rustc`. The builds still
+            // run in the project directory, only the code is copied.
+            Err(_) => {
+                let name = path.file_name().context("path has no file name")?;
+                let copy = dir.path().join(name);
+                if path.is_dir() {
+                    copy_dir(&path, &copy)?;
+                } else {
+                    fs::copy(&path, &copy)
+                        .with_context(|| format!("copying {}", path.display()))?;
+                }
+                options.path = copy;
+            }
+        }
+
+        // Relative scripts are looked up in the project directory, which may have just changed.
+        for script in [&mut options.script_path, &mut options.script_path_lints]
+            .into_iter()
+            .flatten()
+        {
+            if script.is_relative() {
+                *script = root.join(&*script);
+            }
+        }
+
+        info!("Minimizing a copy in {}", dir.path().display());
+
+        Ok(Self {
+            dir,
+            output,
+            original,
+        })
+    }
+
+    pub(crate) fn original(&self) -> &Options {
+        &self.original
+    }
+
+    /// Writes the current state of the copy to the output directory.
+    pub(crate) fn write_output(&self) -> Result<()> {
+        fs::create_dir_all(&self.output)
+            .with_context(|| format!("creating {}", self.output.display()))?;
+        copy_dir(self.dir.path(), &self.output)
+            .with_context(|| format!("writing output to {}", self.output.display()))?;
+        info!("Wrote the result to {}", self.output.display());
+        Ok(())
+    }
+}
+
+/// Copies a directory, without the `target` directory at the top level.
+pub(crate) fn copy_dir(src: &Path, dest: &Path) -> Result<()> {
+    let walk = walkdir::WalkDir::new(src)
+        .into_iter()
+        .filter_entry(|entry| !(entry.depth() == 1 && entry.file_name() == "target"));
+
+    for entry in walk {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(src)?;
+        let target = dest.join(relative);
+
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target)
+                .with_context(|| format!("creating directory {}", target.display()))?;
+        } else if entry.path().is_file() {
+            fs::copy(entry.path(), &target)
+                .with_context(|| format!("copying {}", entry.path().display()))?;
+        } else {
+            warn!("Not copying {}", entry.path().display());
+        }
+    }
+
+    Ok(())
+}